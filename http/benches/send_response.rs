@@ -0,0 +1,57 @@
+//! Compares `HttpResponse::send_response`, which hands the head and body to the
+//! writer with a single `write_vectored`, with the way responses used to be sent:
+//! clone the response, render it into one `String` and `write!` that.
+//!
+//! Run with `cargo bench -p http`.
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use http::http_response::HttpResponse;
+use std::io::{self, Write};
+
+const SIZES: [usize; 3] = [512, 16 * 1024, 1024 * 1024];
+
+fn response(size: usize) -> HttpResponse {
+    HttpResponse::builder()
+        .header("Content-type", "text/css")
+        .header("Cache-Control", "max-age=3600")
+        .header("Server", "http_server")
+        .body("a".repeat(size))
+}
+
+/// The old serialization path, kept here as the baseline
+fn copy_then_write(response: &HttpResponse, writer: &mut impl Write) -> io::Result<()> {
+    let body = response.body().as_bytes().unwrap().to_vec();
+    let mut res = format!(
+        "{} {} {}",
+        response.version(),
+        response.status(),
+        response.status().reason()
+    );
+    for (name, value) in response.headers().iter() {
+        res.push_str(&format!("\r\n{}: {}", name, value));
+    }
+    res.push_str(&format!("\r\nContent-length: {}\r\n\r\n", body.len()));
+    res.push_str(std::str::from_utf8(&body).unwrap());
+    write!(writer, "{}", res)
+}
+
+fn send_response(c: &mut Criterion) {
+    let mut group = c.benchmark_group("send_response");
+    for &size in SIZES.iter() {
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_function(format!("vectored/{}", size), |b| {
+            b.iter_batched(
+                || response(size),
+                |response| response.send_response(&mut io::sink()).unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+        group.bench_function(format!("copy_then_write/{}", size), |b| {
+            let response = response(size);
+            b.iter(|| copy_then_write(&response, &mut io::sink()).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, send_response);
+criterion_main!(benches);
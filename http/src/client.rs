@@ -0,0 +1,613 @@
+//! A small blocking HTTP/1.1 client
+//!
+//! ```no_run
+//! use http::client::Client;
+//! use std::time::{Duration, Instant};
+//!
+//! let client = Client::new().timeout(Some(Duration::from_secs(5)));
+//! let response = client
+//!     .get("http://localhost:3000/api/shipping/orders")
+//!     .header("Accept", "application/json")
+//!     .send()?;
+//! println!("{:?}", response.body().as_bytes());
+//! # Ok::<(), http::client::ClientError>(())
+//! ```
+//!
+//! Connections are kept open after a response and reused for the next request to
+//! the same host, redirects are followed up to a limit, and cookies set by a server
+//! are sent back to it. Only plain `http://` URLs are supported.
+mod cookies;
+pub use cookies::CookieJar;
+
+use crate::headers::HeaderMap;
+use crate::http_request::{HttpRequest, Method, Version};
+use crate::http_response::HttpResponse;
+use crate::parser::{ParseError, ParseStatus, ResponseParser};
+use crate::uri::{TargetForm, Uri};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Default for `Client::timeout`
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Default for `Client::max_redirects`
+pub const DEFAULT_MAX_REDIRECTS: usize = 10;
+
+/// Why a request failed
+#[derive(Debug)]
+pub enum ClientError {
+    /// The URL isn't an absolute `http://` URL
+    InvalidUrl,
+    /// The server's response couldn't be parsed
+    Parse(ParseError),
+    /// The request took longer than the timeout
+    Timeout,
+    /// More redirects in a row than `Client::max_redirects`
+    TooManyRedirects,
+    /// The connection failed
+    Io(io::Error),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::InvalidUrl => f.write_str("not an absolute http:// URL"),
+            ClientError::Parse(e) => write!(f, "invalid response: {}", e),
+            ClientError::Timeout => f.write_str("request timed out"),
+            ClientError::TooManyRedirects => f.write_str("too many redirects"),
+            ClientError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<ParseError> for ClientError {
+    fn from(e: ParseError) -> Self {
+        ClientError::Parse(e)
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => ClientError::Timeout,
+            _ => ClientError::Io(e),
+        }
+    }
+}
+
+/// A blocking client. It can be shared between threads, requests are made through
+/// `&self`.
+#[derive(Debug)]
+pub struct Client {
+    timeout: Option<Duration>,
+    max_redirects: usize,
+    cookies: Option<Mutex<CookieJar>>,
+    /// Open connections waiting for the next request, by `host:port`
+    idle: Mutex<HashMap<String, Vec<TcpStream>>>,
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self {
+            timeout: Some(DEFAULT_TIMEOUT),
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+            cookies: Some(Mutex::new(CookieJar::new())),
+            idle: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl Client {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Give up on a request that takes longer than `timeout` altogether:
+    /// connecting, sending it, reading the response and following redirects.
+    /// `None` waits forever. Requests can override it.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Follow at most this many redirects per request. Zero hands redirect
+    /// responses back instead of following them.
+    pub fn max_redirects(mut self, max_redirects: usize) -> Self {
+        self.max_redirects = max_redirects;
+        self
+    }
+
+    /// Turn the cookie jar on or off
+    pub fn cookies(mut self, enabled: bool) -> Self {
+        self.cookies = if enabled {
+            Some(Mutex::new(CookieJar::new()))
+        } else {
+            None
+        };
+        self
+    }
+
+    /// The cookies received so far, if the jar is on
+    pub fn cookie_jar(&self) -> Option<MutexGuard<'_, CookieJar>> {
+        self.cookies.as_ref().map(|jar| lock(jar))
+    }
+
+    pub fn get(&self, url: &str) -> RequestBuilder<'_> {
+        self.request(Method::Get, url)
+    }
+
+    pub fn head(&self, url: &str) -> RequestBuilder<'_> {
+        self.request(Method::Head, url)
+    }
+
+    pub fn post(&self, url: &str) -> RequestBuilder<'_> {
+        self.request(Method::Post, url)
+    }
+
+    pub fn put(&self, url: &str) -> RequestBuilder<'_> {
+        self.request(Method::Put, url)
+    }
+
+    pub fn delete(&self, url: &str) -> RequestBuilder<'_> {
+        self.request(Method::Delete, url)
+    }
+
+    /// Start a request with any method
+    pub fn request(&self, method: Method, url: &str) -> RequestBuilder<'_> {
+        RequestBuilder {
+            client: self,
+            method,
+            url: parse_url(url),
+            headers: HeaderMap::new(),
+            body: Vec::new(),
+            timeout: self.timeout,
+        }
+    }
+
+    /// Send one request and read its response, without following redirects
+    fn execute(
+        &self,
+        request: HttpRequest,
+        url: &Uri,
+        deadline: Option<Instant>,
+    ) -> Result<HttpResponse, ClientError> {
+        let key = url
+            .authority()
+            .ok_or(ClientError::InvalidUrl)?
+            .to_ascii_lowercase();
+        let idle = lock(&self.idle).get_mut(&key).and_then(Vec::pop);
+        if let Some(stream) = idle {
+            match self.exchange(stream, &request, &key, deadline) {
+                // The server may have closed the connection while it sat idle. Try
+                // again on a fresh one, which is only safe if repeating the request
+                // does no harm.
+                Err(ClientError::Io(_))
+                | Err(ClientError::Parse(ParseError::Incomplete))
+                    if request.method.is_idempotent() => {}
+                result => return result,
+            }
+        }
+        let stream = connect(url, deadline)?;
+        self.exchange(stream, &request, &key, deadline)
+    }
+
+    /// Write `request` to `stream` and read the response. The connection goes back
+    /// to the idle pool if the server is willing to keep it open.
+    fn exchange(
+        &self,
+        mut stream: TcpStream,
+        request: &HttpRequest,
+        key: &str,
+        deadline: Option<Instant>,
+    ) -> Result<HttpResponse, ClientError> {
+        let mut bytes = Vec::new();
+        request.write_to(&mut bytes)?;
+        let mut written = 0;
+        while written < bytes.len() {
+            stream.set_write_timeout(remaining(deadline)?)?;
+            match stream.write(&bytes[written..]) {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero).into()),
+                Ok(n) => written += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let mut parser = ResponseParser::new();
+        parser.set_request_method(request.method.clone());
+        let mut buf = [0_u8; 8192];
+        let mut status: ParseStatus<HttpResponse> = ParseStatus::Incomplete;
+        loop {
+            match status {
+                // Interim responses such as 100 Continue are skipped
+                ParseStatus::Complete(response, leftover)
+                    if response.status().is_informational() =>
+                {
+                    status = parser.feed(&leftover)?;
+                }
+                ParseStatus::Complete(response, leftover) => {
+                    if leftover.is_empty() && keeps_alive(&response) {
+                        lock(&self.idle)
+                            .entry(key.to_string())
+                            .or_default()
+                            .push(stream);
+                    }
+                    return Ok(response);
+                }
+                ParseStatus::Incomplete => {
+                    // A server trickling bytes mustn't stretch the request past
+                    // its deadline
+                    stream.set_read_timeout(remaining(deadline)?)?;
+                    let bytes_read = match stream.read(&mut buf) {
+                        Ok(0) => return Ok(parser.eof()?),
+                        Ok(bytes_read) => bytes_read,
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(e) => return Err(e.into()),
+                    };
+                    status = parser.feed(&buf[..bytes_read])?;
+                }
+            }
+        }
+    }
+}
+
+/// A request being put together. Nothing is sent until `send`.
+#[derive(Debug)]
+pub struct RequestBuilder<'a> {
+    client: &'a Client,
+    method: Method,
+    url: Result<Uri, ClientError>,
+    headers: HeaderMap,
+    body: Vec<u8>,
+    timeout: Option<Duration>,
+}
+
+impl<'a> RequestBuilder<'a> {
+    /// Add a header field, keeping any earlier values of the same name
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Override the client's timeout for this request
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Send the request, follow any redirects and return the final response
+    pub fn send(self) -> Result<HttpResponse, ClientError> {
+        let RequestBuilder {
+            client,
+            mut method,
+            url,
+            mut headers,
+            mut body,
+            timeout,
+        } = self;
+        let mut url = url?;
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut redirects = 0;
+        loop {
+            let response = client.execute(
+                build_request(client, &method, &url, &headers, &body),
+                &url,
+                deadline,
+            )?;
+            if let Some(jar) = &client.cookies {
+                let mut jar = lock(jar);
+                for set_cookie in response.headers().get_all("Set-Cookie") {
+                    jar.store(&url, set_cookie);
+                }
+            }
+            let code = response.status().as_u16();
+            let location = match response.headers().get("Location") {
+                Some(location) if [301, 302, 303, 307, 308].contains(&code) => location,
+                _ => return Ok(response),
+            };
+            if client.max_redirects == 0 {
+                return Ok(response);
+            }
+            if redirects == client.max_redirects {
+                return Err(ClientError::TooManyRedirects);
+            }
+            redirects += 1;
+            let next = resolve(&url, location)?;
+            // 303 always means "GET the other resource", and by long standing
+            // practice so do 301 and 302 in answer to a POST
+            if code == 303 && method != Method::Head
+                || code <= 302 && method == Method::Post
+            {
+                method = Method::Get;
+                body.clear();
+                for name in ["Content-Length", "Content-Type", "Transfer-Encoding"].iter()
+                {
+                    headers.remove(name);
+                }
+            }
+            // Credentials are for the host they were given to
+            if next.authority() != url.authority() {
+                headers.remove("Authorization");
+                headers.remove("Cookie");
+            }
+            url = next;
+        }
+    }
+}
+
+/// Assemble the request for `url`: an origin-form target plus the Host and Cookie
+/// headers, unless they were set by hand
+fn build_request(
+    client: &Client,
+    method: &Method,
+    url: &Uri,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> HttpRequest {
+    let target = url.path_and_query().parse().expect("path of a valid URL");
+    let mut request = HttpRequest::new(method.clone(), target);
+    if !headers.contains("Host") {
+        request
+            .headers
+            .append("Host", url.authority().unwrap_or_default());
+    }
+    for (name, value) in headers.iter() {
+        request.headers.append(name, value);
+    }
+    if !headers.contains("Cookie") {
+        let cookies = client
+            .cookies
+            .as_ref()
+            .and_then(|jar| lock(jar).header_for(url));
+        if let Some(cookies) = cookies {
+            request.headers.append("Cookie", cookies);
+        }
+    }
+    request.body = body.to_vec();
+    request
+}
+
+/// Parse an absolute `http://` URL
+fn parse_url(url: &str) -> Result<Uri, ClientError> {
+    let url: Uri = url.parse().map_err(|_| ClientError::InvalidUrl)?;
+    if url.form() == TargetForm::Absolute && url.scheme() == Some("http") {
+        Ok(url)
+    } else {
+        Err(ClientError::InvalidUrl)
+    }
+}
+
+/// Resolve a Location header against the URL that was requested
+fn resolve(base: &Uri, location: &str) -> Result<Uri, ClientError> {
+    let authority = base.authority().ok_or(ClientError::InvalidUrl)?;
+    let absolute = if location.contains("://") {
+        location.to_string()
+    } else if location.starts_with("//") {
+        format!("http:{}", location)
+    } else if location.starts_with('/') {
+        format!("http://{}{}", authority, location)
+    } else {
+        // Relative to the directory of the current path
+        let path = base.path();
+        let directory = &path[..path.rfind('/').map_or(0, |index| index + 1)];
+        format!("http://{}{}{}", authority, directory, location)
+    };
+    parse_url(&absolute)
+}
+
+/// Whether the server lets the connection stay open after `response`
+fn keeps_alive(response: &HttpResponse) -> bool {
+    let headers = response.headers();
+    match response.version() {
+        Version::V1_1 => !headers.has_token("Connection", "close"),
+        _ => headers.has_token("Connection", "keep-alive"),
+    }
+}
+
+/// Time left until `deadline`, for a socket timeout. Fails once it has passed.
+fn remaining(deadline: Option<Instant>) -> Result<Option<Duration>, ClientError> {
+    match deadline {
+        None => Ok(None),
+        Some(deadline) => {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                Err(ClientError::Timeout)
+            } else {
+                Ok(Some(left))
+            }
+        }
+    }
+}
+
+fn connect(url: &Uri, deadline: Option<Instant>) -> Result<TcpStream, ClientError> {
+    let host = url.host().ok_or(ClientError::InvalidUrl)?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let port = url.port().unwrap_or(80);
+    let mut last_error = None;
+    for addr in (host, port).to_socket_addrs()? {
+        let result = match remaining(deadline)? {
+            Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+            None => TcpStream::connect(addr),
+        };
+        match result {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error
+        .unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "host not found"))
+        .into())
+}
+
+/// Lock a mutex, carrying on if another thread panicked while holding it
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_request::HttpRequest;
+    use crate::parser::RequestParser;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    /// Serve every connection on its own thread, answering each request with
+    /// `respond`. Returns the base URL and a count of accepted connections.
+    fn serve(respond: fn(&HttpRequest) -> String) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = Arc::clone(&connections);
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                thread::spawn(move || {
+                    let mut parser = RequestParser::new();
+                    let mut buf = [0_u8; 1024];
+                    loop {
+                        let bytes_read = match stream.read(&mut buf) {
+                            Ok(0) | Err(_) => return,
+                            Ok(bytes_read) => bytes_read,
+                        };
+                        let mut status = parser.feed(&buf[..bytes_read]).unwrap();
+                        while let ParseStatus::Complete(request, _) = status {
+                            stream.write_all(respond(&request).as_bytes()).unwrap();
+                            status = parser.feed(&[]).unwrap();
+                        }
+                    }
+                });
+            }
+        });
+        (base, connections)
+    }
+
+    fn echo_path(request: &HttpRequest) -> String {
+        let path = request.resource.path();
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+            path.len(),
+            path
+        )
+    }
+
+    #[test]
+    fn reuses_connections() {
+        let (base, connections) = serve(echo_path);
+        let client = Client::new();
+        for path in ["/a", "/b", "/c"].iter() {
+            let response = client.get(&format!("{}{}", base, path)).send().unwrap();
+            assert_eq!(response.body().as_bytes(), Some(path.as_bytes()));
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn follows_redirects_up_to_the_limit() {
+        fn redirect(request: &HttpRequest) -> String {
+            match request.resource.path() {
+                "/old" => "HTTP/1.1 303 See Other\r\nLocation: new\r\n\
+                    Content-Length: 0\r\n\r\n"
+                    .to_string(),
+                "/loop" => "HTTP/1.1 302 Found\r\nLocation: /loop\r\n\
+                    Content-Length: 0\r\n\r\n"
+                    .to_string(),
+                _ => format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                    request.method.as_str().len(),
+                    request.method.as_str()
+                ),
+            }
+        }
+        let (base, _) = serve(redirect);
+        let client = Client::new().max_redirects(3);
+        let response = client
+            .post(&format!("{}/old", base))
+            .body("x")
+            .send()
+            .unwrap();
+        assert_eq!(response.body().as_bytes(), Some(&b"GET"[..]));
+
+        let result = client.get(&format!("{}/loop", base)).send();
+        assert!(matches!(result, Err(ClientError::TooManyRedirects)));
+
+        let client = Client::new().max_redirects(0);
+        let response = client.get(&format!("{}/loop", base)).send().unwrap();
+        assert_eq!(response.status().as_u16(), 302);
+    }
+
+    #[test]
+    fn sends_cookies_back() {
+        fn login(request: &HttpRequest) -> String {
+            let cookie = request.headers.get("Cookie").unwrap_or("none");
+            format!(
+                "HTTP/1.1 200 OK\r\nSet-Cookie: session=abc; Path=/\r\n\
+                    Content-Length: {}\r\n\r\n{}",
+                cookie.len(),
+                cookie
+            )
+        }
+        let (base, _) = serve(login);
+        let client = Client::new();
+        let response = client.get(&format!("{}/login", base)).send().unwrap();
+        assert_eq!(response.body().as_bytes(), Some(&b"none"[..]));
+        let response = client.get(&format!("{}/orders", base)).send().unwrap();
+        assert_eq!(response.body().as_bytes(), Some(&b"session=abc"[..]));
+    }
+
+    #[test]
+    fn times_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        // Accepted by the OS but never answered
+        let client = Client::new().timeout(Some(Duration::from_millis(100)));
+        let result = client.get(&url).send();
+        assert!(matches!(result, Err(ClientError::Timeout)));
+        drop(listener);
+    }
+
+    #[test]
+    fn times_out_on_a_server_trickling_its_response() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\n");
+            // Never quiet for long enough to trip a timeout on a single read
+            while stream.write_all(b"X-Slow: 1\r\n").is_ok() {
+                thread::sleep(Duration::from_millis(20));
+            }
+        });
+        let client = Client::new().timeout(Some(Duration::from_millis(200)));
+        let started = Instant::now();
+        let result = client.get(&url).send();
+        assert!(matches!(result, Err(ClientError::Timeout)));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn rejects_unsupported_urls() {
+        let client = Client::new();
+        for url in ["https://example.com/", "/relative", "example.com:80"].iter() {
+            assert!(matches!(
+                client.get(url).send(),
+                Err(ClientError::InvalidUrl)
+            ));
+        }
+    }
+}
@@ -0,0 +1,296 @@
+//! The cookie jar behind `Client`
+//!
+//! Cookies are stored and matched as RFC 6265 describes, minus the parts a plain
+//! HTTP client has no use for: `Secure` cookies are kept but never sent, since
+//! there is no HTTPS, and `HttpOnly` doesn't apply outside a browser.
+use crate::uri::Uri;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, PartialEq)]
+struct Cookie {
+    name: String,
+    value: String,
+    /// Lowercased host or domain the cookie belongs to
+    domain: String,
+    /// Without a Domain attribute a cookie is only sent back to the exact host
+    host_only: bool,
+    path: String,
+    /// `None` for a session cookie, which lasts as long as the jar
+    expires: Option<SystemTime>,
+    secure: bool,
+}
+
+impl Cookie {
+    fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    fn matches(&self, host: &str, path: &str) -> bool {
+        let domain_matches = if self.host_only {
+            host == self.domain
+        } else {
+            domain_match(host, &self.domain)
+        };
+        domain_matches && path_match(path, &self.path)
+    }
+}
+
+/// Cookies received in `Set-Cookie` headers, to be sent back in `Cookie` headers
+#[derive(Debug, Clone, Default)]
+pub struct CookieJar {
+    cookies: Vec<Cookie>,
+}
+
+impl CookieJar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remember the cookie set by a response to `url`. Malformed cookies and
+    /// cookies for a domain other than the one `url` points at are ignored.
+    pub fn store(&mut self, url: &Uri, set_cookie: &str) {
+        let host = match url.host() {
+            Some(host) => host.to_ascii_lowercase(),
+            None => return,
+        };
+        let mut attributes = set_cookie.split(';');
+        let (name, value) = match attributes.next().and_then(|pair| pair.split_once('='))
+        {
+            Some((name, value)) if !name.trim().is_empty() => (name.trim(), value.trim()),
+            _ => return,
+        };
+        let mut cookie = Cookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            domain: host.clone(),
+            host_only: true,
+            path: default_path(url.path()),
+            expires: None,
+            secure: false,
+        };
+        let mut max_age = None;
+        for attribute in attributes {
+            let (key, value) = match attribute.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => (attribute.trim(), ""),
+            };
+            match key.to_ascii_lowercase().as_str() {
+                "domain" if !value.is_empty() => {
+                    let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                    if !domain_match(&host, &domain) {
+                        return;
+                    }
+                    cookie.domain = domain;
+                    cookie.host_only = false;
+                }
+                "path" if value.starts_with('/') => cookie.path = value.to_string(),
+                "max-age" => {
+                    if let Ok(seconds) = value.parse::<i64>() {
+                        max_age = Some(seconds);
+                    }
+                }
+                "expires" => {
+                    if let Some(expires) = parse_http_date(value) {
+                        cookie.expires = Some(expires);
+                    }
+                }
+                "secure" => cookie.secure = true,
+                _ => (),
+            }
+        }
+        // Max-Age wins over Expires
+        let now = SystemTime::now();
+        if let Some(seconds) = max_age {
+            cookie.expires = if seconds <= 0 {
+                Some(UNIX_EPOCH)
+            } else {
+                // Too far off to represent means it lasts as long as the jar
+                now.checked_add(Duration::from_secs(seconds as u64))
+            };
+        }
+        self.cookies.retain(|old| {
+            (&old.name, &old.domain, &old.path)
+                != (&cookie.name, &cookie.domain, &cookie.path)
+                && !old.is_expired(now)
+        });
+        // Setting an expiry date in the past is how a server deletes a cookie
+        if !cookie.is_expired(now) {
+            self.cookies.push(cookie);
+        }
+    }
+
+    /// The value for a `Cookie` header on a request to `url`, or `None` if no cookie
+    /// applies. Cookies with longer paths come first.
+    pub fn header_for(&self, url: &Uri) -> Option<String> {
+        let host = url.host()?.to_ascii_lowercase();
+        let now = SystemTime::now();
+        let mut cookies: Vec<&Cookie> = self
+            .cookies
+            .iter()
+            .filter(|cookie| {
+                !cookie.secure
+                    && !cookie.is_expired(now)
+                    && cookie.matches(&host, url.path())
+            })
+            .collect();
+        if cookies.is_empty() {
+            return None;
+        }
+        cookies.sort_by_key(|cookie| std::cmp::Reverse(cookie.path.len()));
+        let pairs: Vec<String> = cookies
+            .iter()
+            .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+            .collect();
+        Some(pairs.join("; "))
+    }
+
+    /// Value of the cookie called `name` that would be sent to `url`
+    pub fn get(&self, url: &Uri, name: &str) -> Option<&str> {
+        let host = url.host()?.to_ascii_lowercase();
+        let now = SystemTime::now();
+        self.cookies
+            .iter()
+            .filter(|cookie| !cookie.is_expired(now) && cookie.matches(&host, url.path()))
+            .find(|cookie| cookie.name == name)
+            .map(|cookie| cookie.value.as_str())
+    }
+
+    pub fn clear(&mut self) {
+        self.cookies.clear();
+    }
+}
+
+/// RFC 6265, section 5.1.3. IP addresses only match themselves.
+fn domain_match(host: &str, domain: &str) -> bool {
+    host == domain
+        || (host.ends_with(domain)
+            && host[..host.len() - domain.len()].ends_with('.')
+            && host.parse::<std::net::IpAddr>().is_err())
+}
+
+/// RFC 6265, section 5.1.4
+fn path_match(path: &str, cookie_path: &str) -> bool {
+    path == cookie_path
+        || (path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || path[cookie_path.len()..].starts_with('/')))
+}
+
+/// The directory of the request path, RFC 6265 section 5.1.4
+fn default_path(path: &str) -> String {
+    match path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(index) => path[..index].to_string(),
+    }
+}
+
+/// Parse an IMF-fixdate such as `Sun, 06 Nov 1994 08:49:37 GMT`, the only format
+/// servers are supposed to send (RFC 9110, section 5.6.7)
+fn parse_http_date(date: &str) -> Option<SystemTime> {
+    let parts: Vec<&str> = date.split_whitespace().collect();
+    let (day, month, year, time) = match parts[..] {
+        [_, day, month, year, time, "GMT"] => (day, month, year, time),
+        _ => return None,
+    };
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov",
+        "Dec",
+    ];
+    let month = MONTHS.iter().position(|name| *name == month)? as i64 + 1;
+    let day: i64 = day.parse().ok()?;
+    let year: i64 = year.parse().ok()?;
+    let mut hms = time.split(':').map(|part| part.parse::<i64>().ok());
+    let (hours, minutes, seconds) = (hms.next()??, hms.next()??, hms.next()??);
+    if !(1..=31).contains(&day) || hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+    // Years beyond what fits in a SystemTime make the date invalid
+    let days = days_from_civil(year, month, day)?;
+    let timestamp = days
+        .checked_mul(86_400)?
+        .checked_add(hours * 3_600 + minutes * 60 + seconds)?;
+    if timestamp < 0 {
+        return Some(UNIX_EPOCH);
+    }
+    UNIX_EPOCH.checked_add(Duration::from_secs(timestamp as u64))
+}
+
+/// Days between 1970-01-01 and the given date of the proleptic Gregorian calendar,
+/// or `None` if that doesn't fit in an `i64`
+fn days_from_civil(year: i64, month: i64, day: i64) -> Option<i64> {
+    let year = if month <= 2 { year.checked_sub(1)? } else { year };
+    let era = if year >= 0 { year } else { year.checked_sub(399)? } / 400;
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era =
+        year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era.checked_mul(146_097)?.checked_add(day_of_era - 719_468)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(s: &str) -> Uri {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn sends_cookies_back_where_they_apply() {
+        let mut jar = CookieJar::new();
+        let login = url("http://shop.example.com/account/login");
+        jar.store(&login, "session=abc; Path=/; HttpOnly");
+        jar.store(&login, "theme=dark");
+        jar.store(&login, "wide=1; Domain=.example.com; Path=/");
+        jar.store(&login, "token=t; Secure");
+        jar.store(&login, "evil=1; Domain=other.com");
+
+        let header = jar.header_for(&url("http://shop.example.com/account/orders"));
+        assert_eq!(header.as_deref(), Some("theme=dark; session=abc; wide=1"));
+        let header = jar.header_for(&url("http://shop.example.com/"));
+        assert_eq!(header.as_deref(), Some("session=abc; wide=1"));
+        let header = jar.header_for(&url("http://api.example.com/"));
+        assert_eq!(header.as_deref(), Some("wide=1"));
+        assert_eq!(jar.header_for(&url("http://other.com/")), None);
+        assert_eq!(jar.get(&login, "session"), Some("abc"));
+    }
+
+    #[test]
+    fn replaces_and_expires_cookies() {
+        let mut jar = CookieJar::new();
+        let site = url("http://localhost:3000/");
+        jar.store(&site, "a=1");
+        jar.store(&site, "a=2");
+        jar.store(&site, "b=1; Max-Age=60");
+        assert_eq!(jar.header_for(&site).as_deref(), Some("a=2; b=1"));
+
+        jar.store(&site, "a=; Expires=Thu, 01 Jan 1970 00:00:00 GMT");
+        jar.store(&site, "b=; Max-Age=0");
+        assert_eq!(jar.header_for(&site), None);
+
+        jar.store(&site, "c=1; Expires=Fri, 01 Jan 2100 00:00:00 GMT");
+        assert_eq!(jar.get(&site, "c"), Some("1"));
+    }
+
+    #[test]
+    fn survives_dates_out_of_range() {
+        let mut jar = CookieJar::new();
+        let site = url("http://localhost:3000/");
+        jar.store(&site, "a=1; Max-Age=9223372036854775807");
+        assert_eq!(jar.get(&site, "a"), Some("1"));
+
+        let far = "Fri, 01 Jan 99999999999999999 00:00:00 GMT";
+        assert_eq!(parse_http_date(far), None);
+        jar.store(&site, &format!("b=1; Expires={}", far));
+        assert_eq!(jar.get(&site, "b"), Some("1"));
+        let before = "Fri, 01 Jan -9223372036854775808 00:00:00 GMT";
+        assert_eq!(parse_http_date(before), None);
+    }
+
+    #[test]
+    fn parses_http_dates() {
+        let date = parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT").unwrap();
+        assert_eq!(date, UNIX_EPOCH + Duration::from_secs(784_111_777));
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+    }
+}
@@ -0,0 +1,214 @@
+//! A header collection shared by `HttpRequest` and `HttpResponse`
+//!
+//! Header names are case-insensitive, so "content-type" finds a "Content-Type"
+//! header. Each name may appear several times (think `Set-Cookie`), and the original
+//! spelling and order of the fields is kept so a message serializes the way it was
+//! built.
+use std::iter::FromIterator;
+
+/// Ordered, case-insensitive, multi-valued map of header fields
+#[derive(Debug, Clone, Default)]
+pub struct HeaderMap {
+    entries: Vec<(String, String)>,
+}
+
+impl HeaderMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// First value stored under `name`
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.position(name)
+            .map(|index| self.entries[index].1.as_str())
+    }
+
+    /// Every value stored under `name`, in the order they were added
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Whether the comma separated list in the `name` fields holds `token`, ignoring
+    /// case, as in `Connection: keep-alive, Upgrade`
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name)
+            .flat_map(|value| value.split(','))
+            .any(|item| item.trim().eq_ignore_ascii_case(token))
+    }
+
+    /// Set `name` to `value`, replacing any values it already had. The field keeps
+    /// the position of the first value it replaces.
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        let name = name.into();
+        let value = trim_ows(value.into());
+        match self.position(&name) {
+            Some(index) => {
+                self.entries[index].1 = value;
+                let mut current = 0;
+                self.entries.retain(|(key, _)| {
+                    let keep = current <= index || !key.eq_ignore_ascii_case(&name);
+                    current += 1;
+                    keep
+                });
+            }
+            None => self.entries.push((name, value)),
+        }
+    }
+
+    /// Add another value for `name`, keeping the ones already there
+    pub fn append(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.entries.push((name.into(), trim_ows(value.into())));
+    }
+
+    /// Remove every value stored under `name` and return the first one
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let first = self
+            .position(name)
+            .map(|index| self.entries.remove(index).1);
+        self.entries
+            .retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+        first
+    }
+
+    /// Number of fields, counting each value of a repeated header
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// All fields as (name, value) pairs in insertion order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// Continue the value of the last field with an obsolete folded line, joining
+    /// the two with a single space. Returns false if there is no field to continue.
+    pub(crate) fn extend_last(&mut self, continuation: &str) -> bool {
+        match self.entries.last_mut() {
+            Some((_, value)) => {
+                let continuation = continuation.trim_matches([' ', '\t']);
+                if !continuation.is_empty() {
+                    if !value.is_empty() {
+                        value.push(' ');
+                    }
+                    value.push_str(continuation);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|(key, _)| key.eq_ignore_ascii_case(name))
+    }
+}
+
+/// Two maps are equal when every name has the same values in the same order.
+/// Neither the spelling of names nor the relative order of different names matters.
+impl PartialEq for HeaderMap {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .all(|(name, _)| self.get_all(name).eq(other.get_all(name)))
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for HeaderMap {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut headers = HeaderMap::new();
+        for (name, value) in iter {
+            headers.append(name, value);
+        }
+        headers
+    }
+}
+
+/// Strip the optional whitespace (spaces and tabs) around a field value
+fn trim_ows(value: String) -> String {
+    let trimmed = value.trim_matches(|c| c == ' ' || c == '\t');
+    if trimmed.len() == value.len() {
+        value
+    } else {
+        trimmed.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup_ignores_case_and_trims_values() {
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Type", " text/html\t");
+        assert_eq!(headers.get("content-type"), Some("text/html"));
+        assert_eq!(headers.get("CONTENT-TYPE"), Some("text/html"));
+        assert!(headers.contains("Content-type"));
+        assert_eq!(headers.get("Content-Length"), None);
+
+        headers.append("Connection", "Upgrade");
+        headers.append("connection", " Keep-Alive ,TE");
+        assert!(headers.has_token("Connection", "keep-alive"));
+        assert!(headers.has_token("Connection", "te"));
+        assert!(!headers.has_token("Connection", "close"));
+    }
+
+    #[test]
+    fn repeated_headers_keep_every_value() {
+        let mut headers = HeaderMap::new();
+        headers.append("Set-Cookie", "a=1");
+        headers.append("Content-Type", "text/html");
+        headers.append("set-cookie", "b=2");
+        assert_eq!(headers.get("Set-Cookie"), Some("a=1"));
+        assert_eq!(
+            headers.get_all("Set-Cookie").collect::<Vec<_>>(),
+            ["a=1", "b=2"]
+        );
+        assert_eq!(headers.len(), 3);
+
+        headers.insert("Set-Cookie", "c=3");
+        assert_eq!(
+            headers.iter().collect::<Vec<_>>(),
+            [("Set-Cookie", "c=3"), ("Content-Type", "text/html")]
+        );
+
+        assert_eq!(headers.remove("SET-COOKIE"), Some("c=3".to_string()));
+        assert_eq!(headers.remove("Set-Cookie"), None);
+        assert_eq!(headers.len(), 1);
+    }
+
+    #[test]
+    fn equality_ignores_order_across_names() {
+        let a: HeaderMap = vec![("Accept", "*/*"), ("Host", "localhost")]
+            .into_iter()
+            .collect();
+        let b: HeaderMap = vec![("host", "localhost"), ("Accept", "*/*")]
+            .into_iter()
+            .collect();
+        assert_eq!(a, b);
+
+        let c: HeaderMap = vec![("Accept", "text/html"), ("Accept", "*/*")]
+            .into_iter()
+            .collect();
+        let d: HeaderMap = vec![("Accept", "*/*"), ("Accept", "text/html")]
+            .into_iter()
+            .collect();
+        assert_ne!(c, d);
+    }
+}
@@ -1,167 +1,268 @@
 //! Convert incoming HTTP requests into struct HttpRequest
 #![allow(unused, non_snake_case)]
+pub mod chunked;
+
+use crate::headers::HeaderMap;
+use crate::http_response::chunked::ChunkedEncoder;
+use crate::parser::{is_token, ParseError, ParseStatus, RequestParser};
+use crate::uri::Uri;
 use std::{
-    collections::{HashMap, HashSet},
-    iter::FromIterator,
+    convert::TryFrom,
+    fmt,
+    io::{self, Write},
+    str::FromStr,
 };
 /// Type representing HTTP Request
+#[derive(Debug)]
 pub struct HttpRequest {
     /// The HTTP verb to be used
     pub method: Method,
     /// The HTTP Version to be used
     pub version: Version,
     /// The URL requested for
-    pub resource: Resource,
+    pub resource: Uri,
     /// Additional headers identified by colon separated strings
-    pub headers: HashMap<String, String>,
-    /// Optional message body: POST requests may have a body whereas GET requests doesn't
-    /// which is why body is an Option\<String\>
-    pub body: Option<String>,
+    pub headers: HeaderMap,
+    /// Message body exactly as framed by Content-Length: POST requests may have a body
+    /// whereas GET requests usually don't, in which case it is empty. The body may be
+    /// binary, use `text()` to read it as a string.
+    pub body: Vec<u8>,
+    /// Trailer fields sent after a chunked body
+    pub trailers: HeaderMap,
 }
-/// A unit struct that holds a URL
-#[derive(Debug, PartialEq)]
-pub struct Resource(String);
-
-/// `Method::Get` and `Method::Post` represent HTTP GET and POST methods
-/// respectively. We derive `PartialEq` in order to allow comparison of
-/// enum variants to generated variants in tests. Debug is derived for
-/// future cases and is not necessary. `From<&str>` trait is derived to
-/// directly generate the enum from a string.
-#[derive(Debug, PartialEq)]
+/// The methods defined in RFC 9110, section 9 each have a variant of their own. Any
+/// other method that is a valid token (WebDAV's PROPFIND for instance) is kept as an
+/// `Extension`. Methods are case-sensitive, so "get" is an extension method too.
+/// `FromStr` and `Display` convert to and from the method token.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Method {
     Get,
+    Head,
     Post,
-    Invalid,
+    Put,
+    Delete,
+    Connect,
+    Options,
+    Trace,
+    Patch,
+    Extension(String),
 }
+/// V1_0 represents HTTP version 1.0
 /// V1_1 represents HTTP version 1.1
 /// V2_0 represents HTTP version 2.0
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum Version {
+    V1_0,
+    #[default]
     V1_1,
     V2_0,
-    Invalid,
+}
+
+impl Method {
+    /// The method token as it appears on the request line
+    pub fn as_str(&self) -> &str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Connect => "CONNECT",
+            Method::Options => "OPTIONS",
+            Method::Trace => "TRACE",
+            Method::Patch => "PATCH",
+            Method::Extension(token) => token,
+        }
+    }
+
+    /// Safe methods are essentially read-only (RFC 9110, section 9.2.1). Nothing is
+    /// known about extension methods, so they are never considered safe.
+    pub fn is_safe(&self) -> bool {
+        matches!(
+            self,
+            Method::Get | Method::Head | Method::Options | Method::Trace
+        )
+    }
+
+    /// Idempotent methods can be retried without changing the outcome (RFC 9110,
+    /// section 9.2.2)
+    pub fn is_idempotent(&self) -> bool {
+        self.is_safe() || matches!(self, Method::Put | Method::Delete)
+    }
+
+    /// Responses to these methods may be stored by caches (RFC 9110, section 9.2.3).
+    /// POST responses are only cacheable with explicit freshness information.
+    pub fn is_cacheable(&self) -> bool {
+        matches!(self, Method::Get | Method::Head | Method::Post)
+    }
 }
 
 /// Get a HTTP Method from string slice
-impl From<&str> for Method {
-    fn from(s: &str) -> Self {
-        match s {
+impl FromStr for Method {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let method = match s {
             "GET" => Method::Get,
+            "HEAD" => Method::Head,
             "POST" => Method::Post,
-            _ => Method::Invalid,
-        }
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            "CONNECT" => Method::Connect,
+            "OPTIONS" => Method::Options,
+            "TRACE" => Method::Trace,
+            "PATCH" => Method::Patch,
+            token if is_token(token) => Method::Extension(token.to_string()),
+            _ => return Err(ParseError::InvalidMethod),
+        };
+        Ok(method)
     }
 }
-/// Get HTTP Version from string slice
-impl From<&str> for Version {
-    fn from(s: &str) -> Self {
-        match s {
-            "HTTP/1.1" => Version::V1_1,
-            "HTTP/2" => Version::V2_0,
-            _ => Version::Invalid,
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+impl Version {
+    /// The version as it appears on a request or status line
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Version::V1_0 => "HTTP/1.0",
+            Version::V1_1 => "HTTP/1.1",
+            Version::V2_0 => "HTTP/2",
         }
     }
 }
 
-impl From<&str> for Resource {
-    fn from(s: &str) -> Self {
-        Resource(s.to_string())
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
     }
 }
 
-impl HttpRequest {
-    pub fn get_url(&self) -> String {
-        self.resource.0.clone()
+/// Get HTTP Version from string slice. Anything that doesn't look like `HTTP/x.y` is
+/// malformed, whereas a well formed version we don't know about is unsupported.
+impl FromStr for Version {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "HTTP/1.0" => Ok(Version::V1_0),
+            "HTTP/1.1" => Ok(Version::V1_1),
+            "HTTP/2" | "HTTP/2.0" => Ok(Version::V2_0),
+            _ => match s.as_bytes() {
+                [b'H', b'T', b'T', b'P', b'/', major, b'.', minor]
+                    if major.is_ascii_digit() && minor.is_ascii_digit() =>
+                {
+                    Err(ParseError::UnsupportedVersion)
+                }
+                _ => Err(ParseError::MalformedRequestLine),
+            },
+        }
     }
 }
 
-/// The main logic of this module and the crate http. That is to parse
-/// and create a HttpRequest object from bytes or string slice in this case
-// Check From<&str> impl
-impl From<&String> for HttpRequest {
-    /// Read each line from string, delimited by CRLF \r\n,
-    /// and identify if the line begins with "HTTP" (Request Line)
-    /// in which case it would be definitely an HTTP Request, then proceed to
-    /// extract the version and method from the same line.
-    ///
-    /// If the line is a header line, then index the left subslice to the ":"
-    /// as a `key` and the right sublice as a `value` for the headers hashmap.
-    ///
-    /// If line is empty \n\r, treat these as line separators
-    ///
-    /// Optionally check for a message body, if present, store it.
-    /// \r\n\r\n marks the end of the request
-
-    fn from(s: &String) -> Self {
-        // the "p" stands for "parsed", so read p_method as "parsed_method" and so on..
-        let mut p_method = Method::Invalid;
-        let mut p_version = Version::Invalid;
-        let mut p_resource = Resource("".to_string());
-        let mut p_headers: HashMap<String, String> = HashMap::new();
-        let mut p_body: Option<String> = None;
-
-        // Read each line of the incoming stream
-        for line in s.lines() {
-            // Process for HTTP request line
-            if line.contains("HTTP") {
-                let (_method, _version, _resource) = process_request_line(&line);
-                p_method = _method;
-                p_version = _version;
-                p_resource = _resource;
-                continue;
-            }
+impl HttpRequest {
+    /// An HTTP/1.1 request for `resource` without headers or body
+    pub fn new(method: Method, resource: Uri) -> Self {
+        HttpRequest {
+            method,
+            version: Version::V1_1,
+            resource,
+            headers: HeaderMap::new(),
+            body: Vec::new(),
+            trailers: HeaderMap::new(),
+        }
+    }
 
-            // Process for header line
-            if line.contains(":") {
-                let (key, value) = process_header_line(&line);
-                p_headers.insert(key, value);
-                continue;
-            }
+    /// Serialize the request for sending. Unless the headers already frame the body,
+    /// a Content-Length is added for a body, or for an empty one on methods that
+    /// usually have one. Trailers can only follow a chunked body, so having any
+    /// makes the request chunked.
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        let te_chunked = self
+            .headers
+            .get_all("Transfer-Encoding")
+            .flat_map(|value| value.split(','))
+            .last()
+            .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked"));
+        let chunked = te_chunked || !self.trailers.is_empty();
+        let mut head = format!(
+            "{} {} {}\r\n",
+            self.method,
+            self.resource.request_target(),
+            self.version
+        );
+        for (name, value) in self.headers.iter() {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        if chunked && !te_chunked {
+            head.push_str("Transfer-Encoding: chunked\r\n");
+        }
+        let expects_body = matches!(self.method, Method::Post | Method::Put | Method::Patch);
+        if !chunked
+            && !self.headers.contains("Content-Length")
+            && (!self.body.is_empty() || expects_body)
+        {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        head.push_str("\r\n");
+        writer.write_all(head.as_bytes())?;
+        if chunked {
+            let mut encoder = ChunkedEncoder::new(&mut *writer);
+            encoder.write_all(&self.body)?;
+            encoder.finish(&self.trailers)?;
+        } else {
+            writer.write_all(&self.body)?;
+        }
+        writer.flush()
+    }
 
-            // Ignore empty lines.
-            if line.len() != 0 {
-                // Maybe there is a body
-                if let Some(mut previous_lines) = p_body {
-                    previous_lines.push_str(line);
-                    p_body = Some(previous_lines);
+    /// The request target as it was sent, after normalization
+    pub fn get_url(&self) -> String {
+        self.resource.to_string()
+    }
+
+    /// The body as text. Fails if the Content-Type names a charset other than UTF-8
+    /// (or its subset US-ASCII), or if the bytes aren't valid UTF-8.
+    pub fn text(&self) -> Result<&str, ParseError> {
+        let charset = self.headers.get("Content-Type").and_then(|content_type| {
+            content_type.split(';').skip(1).find_map(|param| {
+                let (name, value) = param.split_at(param.find('=')?);
+                if name.trim().eq_ignore_ascii_case("charset") {
+                    Some(value[1..].trim().trim_matches('"'))
                 } else {
-                    p_body = Some(String::from(line));
+                    None
                 }
+            })
+        });
+        match charset {
+            Some(charset)
+                if !charset.eq_ignore_ascii_case("utf-8")
+                    && !charset.eq_ignore_ascii_case("us-ascii") =>
+            {
+                Err(ParseError::UnsupportedCharset)
             }
-        }
-        HttpRequest {
-            method: p_method,
-            version: p_version,
-            resource: p_resource,
-            headers: p_headers,
-            body: p_body,
+            _ => std::str::from_utf8(&self.body).map_err(|_| ParseError::InvalidUtf8),
         }
     }
 }
-/// Parses the HTTP request line and returns Method, Version and Resource
-fn process_request_line(req: &str) -> (Method, Version, Resource) {
-    // Safety: We can unwrap here safely because this message is only called
-    // on valid HTTP requests
-    let mut req = req.split_whitespace().into_iter();
-    let method = req.next().unwrap();
-    let resource = req.next().unwrap();
-    let version = req.next().unwrap();
-
-    let method = Method::from(method);
-
-    let resource = Resource::from(resource);
 
-    let version = Version::from(version);
-
-    (method, version, resource)
-}
+/// The main logic of this module and the crate http. That is to parse
+/// and create a HttpRequest object from raw bytes
+impl TryFrom<&[u8]> for HttpRequest {
+    type Error = ParseError;
 
-/// Converts a "Key:Value" String into tuple
-fn process_header_line(header: &str) -> (String, String) {
-    let split_index = header.find(':').unwrap();
-    let k = String::from(&header[..split_index]);
-    let v = String::from(&header[split_index + 1..]);
-    (k, v)
+    /// Parse a single, complete request. Any bytes past the end of the request are
+    /// ignored; use `parser::RequestParser` directly to keep them for the next one
+    /// or to parse a request that arrives in several pieces.
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        match RequestParser::new().feed(bytes)? {
+            ParseStatus::Complete(request, _) => Ok(request),
+            ParseStatus::Incomplete => Err(ParseError::Incomplete),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -171,13 +272,16 @@ mod tests {
     use super::*;
     #[test]
     fn test_FromTrait_impl() {
-        let get: Method = "GET".into();
+        let get: Method = "GET".parse().unwrap();
         assert_eq!(Method::Get, get);
 
-        let post = Method::from("POST");
+        let post = Method::from_str("POST").unwrap();
         assert_eq!(Method::Post, post);
 
-        assert_eq!(Version::from("HTTP/2"), Version::V2_0);
+        assert_eq!("HTTP/2".parse(), Ok(Version::V2_0));
+        assert_eq!("HTTP/1.0".parse(), Ok(Version::V1_0));
+        assert_eq!("HTTP/3.0".parse::<Version>(), Err(ParseError::UnsupportedVersion));
+        assert_eq!("HTTP/1".parse::<Version>(), Err(ParseError::MalformedRequestLine));
     }
 
     #[test]
@@ -187,23 +291,123 @@ mod tests {
             "GET", "/index.html", "HTTP/1.1\r\n", "User-Agent: curl/1.1.1\r\n",
         );
 
-        let test = HttpRequest::from(&s);
+        let test = HttpRequest::try_from(s.as_bytes()).unwrap();
 
         // expected values
-        let _method = Method::from("GET");
-        let _version = Version::from("HTTP/1.1");
-        let _resource = Resource::from("/index.html");
-        let mut _headers: HashMap<String, String> = HashMap::new();
-        // mind the leading whitespace in the following values
-        _headers.insert("User-Agent".into(), " curl/1.1.1".into());
-        _headers.insert("Accept".into(), " */*".into());
-        _headers.insert("Host".into()," localhost:3000".into());
+        let _method = Method::Get;
+        let _version = Version::V1_1;
+        let _resource: Uri = "/index.html".parse().unwrap();
+        let mut _headers = HeaderMap::new();
+        // the whitespace after the colon is not part of the value
+        _headers.insert("User-Agent", "curl/1.1.1");
+        _headers.insert("Accept", "*/*");
+        _headers.insert("Host", "localhost:3000");
         // expected values 
 
         assert_eq!(test.method, _method);
         assert_eq!(test.version, _version);
         assert_eq!(test.resource, _resource);
         assert_eq!(test.headers, _headers);
+        assert_eq!(test.headers.get("user-agent"), Some("curl/1.1.1"));
+
+    }
+
+    #[test]
+    fn test_tryfrom_errors() {
+        // Used to panic: any line mentioning HTTP was taken for the request line
+        let s = b"GET / HTTP/1.1\r\nReferer: HTTP\r\n\r\n";
+        assert!(HttpRequest::try_from(&s[..]).is_ok());
+
+        let s = b"GET / HTTP/1.1\r\nX-Name: \xff\r\n\r\n";
+        assert_eq!(HttpRequest::try_from(&s[..]).unwrap_err(), ParseError::InvalidUtf8);
+
+        let s = b"GET / HTTP/1.1\r\nHost: localhost";
+        assert_eq!(HttpRequest::try_from(&s[..]).unwrap_err(), ParseError::Incomplete);
+
+        let s = b"GET / HTTP/9.9\r\n\r\n";
+        assert_eq!(
+            HttpRequest::try_from(&s[..]).unwrap_err(),
+            ParseError::UnsupportedVersion
+        );
+    }
+
+    #[test]
+    fn test_method_roundtrip() {
+        let tokens = [
+            "GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH",
+            "PROPFIND", "get",
+        ];
+        for token in tokens.iter() {
+            let method: Method = token.parse().unwrap();
+            assert_eq!(method.to_string(), *token);
+        }
+        assert_eq!("get".parse(), Ok(Method::Extension("get".into())));
+        assert_eq!("GE T".parse::<Method>(), Err(ParseError::InvalidMethod));
+        assert_eq!("".parse::<Method>(), Err(ParseError::InvalidMethod));
+    }
+
+    #[test]
+    fn test_method_properties() {
+        assert!(Method::Get.is_safe() && Method::Get.is_idempotent());
+        assert!(!Method::Put.is_safe() && Method::Put.is_idempotent());
+        assert!(!Method::Post.is_idempotent() && Method::Post.is_cacheable());
+        assert!(!Method::Patch.is_idempotent() && !Method::Patch.is_cacheable());
+        let propfind = Method::Extension("PROPFIND".into());
+        assert!(!propfind.is_safe() && !propfind.is_idempotent() && !propfind.is_cacheable());
+    }
+
+    #[test]
+    fn test_binary_body() {
+        let mut s = b"POST /upload HTTP/1.1\r\nContent-Length: 9\r\n\r\n".to_vec();
+        let body = b"a:b\r\n\0\xff\n\n";
+        s.extend_from_slice(body);
+
+        let test = HttpRequest::try_from(&s[..]).unwrap();
+        assert_eq!(test.body, body.to_vec());
+        assert!(test.headers.get("a").is_none());
+        assert_eq!(test.text(), Err(ParseError::InvalidUtf8));
+    }
+
+    #[test]
+    fn test_text_body() {
+        let s = b"POST / HTTP/1.1\r\nContent-Type: text/plain; charset=\"UTF-8\"\r\n\
+            Content-Length: 6\r\n\r\nh\xc3\xa9llo";
+        assert_eq!(HttpRequest::try_from(&s[..]).unwrap().text(), Ok("héllo"));
+
+        let s = b"POST / HTTP/1.1\r\nContent-Type: text/plain;charset=latin1\r\n\
+            Content-Length: 5\r\n\r\nhello";
+        assert_eq!(
+            HttpRequest::try_from(&s[..]).unwrap().text(),
+            Err(ParseError::UnsupportedCharset)
+        );
+    }
+
+    #[test]
+    fn test_write_to_roundtrip() {
+        let target = "/orders?x=1#top".parse().unwrap();
+        let mut request = HttpRequest::new(Method::Post, target);
+        request.headers.insert("Host", "localhost");
+        request.body = b"\0binary".to_vec();
+        let mut bytes = Vec::new();
+        request.write_to(&mut bytes).unwrap();
+        let expected = b"POST /orders?x=1 HTTP/1.1\r\nHost: localhost\r\n\
+            Content-Length: 7\r\n\r\n\0binary";
+        assert_eq!(bytes, expected.to_vec());
+        let parsed = HttpRequest::try_from(&bytes[..]).unwrap();
+        assert_eq!(parsed.body, request.body);
+
+        request.trailers.insert("X-Checksum", "42");
+        let mut bytes = Vec::new();
+        request.write_to(&mut bytes).unwrap();
+        let parsed = HttpRequest::try_from(&bytes[..]).unwrap();
+        assert_eq!(parsed.headers.get("transfer-encoding"), Some("chunked"));
+        assert_eq!(parsed.body, request.body);
+        assert_eq!(parsed.trailers, request.trailers);
 
+        let mut bytes = Vec::new();
+        HttpRequest::new(Method::Get, "/".parse().unwrap())
+            .write_to(&mut bytes)
+            .unwrap();
+        assert_eq!(bytes, b"GET / HTTP/1.1\r\n\r\n".to_vec());
     }
 }
@@ -0,0 +1,353 @@
+//! Decoding of request bodies sent with `Transfer-Encoding: chunked`
+//!
+//! A chunked body is a series of chunks, each one a hexadecimal size line followed by
+//! that many bytes of data, ended by a zero sized chunk and an optional trailer
+//! section (RFC 9112, section 7.1):
+//!
+//! ```text
+//! 5;name=value\r\n
+//! hello\r\n
+//! 0\r\n
+//! Expires: never\r\n
+//! \r\n
+//! ```
+//!
+//! `ChunkedDecoder` works on whatever part of the body has arrived so far, so it can
+//! sit inside `parser::RequestParser` while the rest is still in flight.
+use crate::headers::HeaderMap;
+use crate::parser::{is_tchar, parse_header_line, ParseError, ParseMode};
+
+/// Longest chunk size line accepted, extensions included
+pub const MAX_CHUNK_LINE: usize = 4096;
+/// Default cap on the decoded body
+pub const DEFAULT_MAX_BODY_SIZE: usize = 8 * 1024 * 1024;
+/// Default cap on the trailer section
+pub const DEFAULT_MAX_TRAILER_SIZE: usize = 8 * 1024;
+
+/// Where the decoder is within the chunked body
+#[derive(Debug, PartialEq)]
+enum State {
+    /// Expecting a chunk size line
+    Size,
+    /// Inside a chunk with this many data bytes left
+    Data(usize),
+    /// Expecting the CRLF that closes a chunk's data
+    DataEnd,
+    /// Reading trailer fields after the last chunk
+    Trailers,
+    Done,
+}
+
+/// Incremental decoder for a chunked body
+#[derive(Debug)]
+pub struct ChunkedDecoder {
+    state: State,
+    max_body_size: usize,
+    max_trailer_size: usize,
+    /// Decoded bytes so far
+    body_size: usize,
+    trailer_size: usize,
+    trailers: HeaderMap,
+    mode: ParseMode,
+}
+
+impl Default for ChunkedDecoder {
+    fn default() -> Self {
+        Self::with_limits(DEFAULT_MAX_BODY_SIZE, DEFAULT_MAX_TRAILER_SIZE)
+    }
+}
+
+impl ChunkedDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A decoder that gives up once the decoded body grows past `max_body_size` bytes
+    /// or the trailer section past `max_trailer_size` bytes
+    pub fn with_limits(max_body_size: usize, max_trailer_size: usize) -> Self {
+        Self {
+            state: State::Size,
+            max_body_size,
+            max_trailer_size,
+            body_size: 0,
+            trailer_size: 0,
+            trailers: HeaderMap::new(),
+            mode: ParseMode::default(),
+        }
+    }
+
+    /// Switch the decoder to `mode`. A strict decoder insists on CRLF line endings.
+    pub fn mode(mut self, mode: ParseMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Decode as much of `input` as possible, appending chunk data to `body`, and
+    /// return how many bytes of `input` were used. Bytes that weren't used (a size
+    /// line cut in half, say) must be passed again, followed by more input. Once
+    /// `is_done` the rest of the input belongs to whatever follows the body.
+    pub fn decode(
+        &mut self,
+        input: &[u8],
+        body: &mut Vec<u8>,
+    ) -> Result<usize, ParseError> {
+        let mut consumed = 0;
+        loop {
+            let rest = &input[consumed..];
+            match self.state {
+                State::Size => {
+                    let line = match take_line(rest, MAX_CHUNK_LINE, self.mode)? {
+                        Some((line, used)) => {
+                            consumed += used;
+                            line
+                        }
+                        None => return Ok(consumed),
+                    };
+                    let size = parse_chunk_size(line)?;
+                    if size > self.max_body_size - self.body_size {
+                        return Err(ParseError::BodyTooLarge);
+                    }
+                    self.state = if size == 0 {
+                        State::Trailers
+                    } else {
+                        State::Data(size)
+                    };
+                }
+                State::Data(remaining) => {
+                    if rest.is_empty() {
+                        return Ok(consumed);
+                    }
+                    let take = remaining.min(rest.len());
+                    body.extend_from_slice(&rest[..take]);
+                    self.body_size += take;
+                    consumed += take;
+                    self.state = if take == remaining {
+                        State::DataEnd
+                    } else {
+                        State::Data(remaining - take)
+                    };
+                }
+                State::DataEnd => {
+                    if rest.starts_with(b"\r\n") {
+                        consumed += 2;
+                    } else if rest.starts_with(b"\n") {
+                        if self.mode == ParseMode::Strict {
+                            return Err(ParseError::BareLf);
+                        }
+                        consumed += 1;
+                    } else if rest.is_empty() || rest == b"\r" {
+                        return Ok(consumed);
+                    } else {
+                        return Err(ParseError::InvalidChunk);
+                    }
+                    self.state = State::Size;
+                }
+                State::Trailers => {
+                    let remaining = self.max_trailer_size - self.trailer_size;
+                    let (line, used) = match take_line(rest, remaining, self.mode) {
+                        Ok(Some(line)) => line,
+                        Ok(None) => return Ok(consumed),
+                        Err(ParseError::ChunkLineTooLong) => {
+                            return Err(ParseError::TrailersTooLarge)
+                        }
+                        Err(e) => return Err(e),
+                    };
+                    consumed += used;
+                    self.trailer_size += used;
+                    if line.is_empty() {
+                        self.state = State::Done;
+                        continue;
+                    }
+                    let line =
+                        std::str::from_utf8(line).map_err(|_| ParseError::InvalidUtf8)?;
+                    let (name, value) = parse_header_line(line, self.mode)?;
+                    self.trailers.append(name, value);
+                }
+                State::Done => return Ok(consumed),
+            }
+        }
+    }
+
+    /// Whether the last chunk and the trailer section have been decoded
+    pub fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    /// The trailer fields received so far
+    pub fn trailers(&self) -> &HeaderMap {
+        &self.trailers
+    }
+
+    /// Take the trailer fields out of the decoder
+    pub fn take_trailers(&mut self) -> HeaderMap {
+        std::mem::take(&mut self.trailers)
+    }
+}
+
+/// Find the next line in `input`, giving it back without its line ending along with
+/// the number of bytes it took up. Fails if no line ending shows up within `max`
+/// bytes.
+fn take_line(
+    input: &[u8],
+    max: usize,
+    mode: ParseMode,
+) -> Result<Option<(&[u8], usize)>, ParseError> {
+    match input.iter().position(|&b| b == b'\n') {
+        Some(end) if end < max => {
+            let line = &input[..end];
+            match line.strip_suffix(b"\r") {
+                Some(line) => Ok(Some((line, end + 1))),
+                None if mode == ParseMode::Strict => Err(ParseError::BareLf),
+                None => Ok(Some((line, end + 1))),
+            }
+        }
+        Some(_) => Err(ParseError::ChunkLineTooLong),
+        None if input.len() >= max => Err(ParseError::ChunkLineTooLong),
+        None => Ok(None),
+    }
+}
+
+/// Parse `chunk-size [ chunk-ext ]`, checking and then ignoring the extensions
+fn parse_chunk_size(line: &[u8]) -> Result<usize, ParseError> {
+    let digits = line.iter().take_while(|b| b.is_ascii_hexdigit()).count();
+    if digits == 0 {
+        return Err(ParseError::InvalidChunk);
+    }
+    let mut size: usize = 0;
+    for &digit in &line[..digits] {
+        let value = (digit as char).to_digit(16).unwrap() as usize;
+        size = size
+            .checked_mul(16)
+            .and_then(|size| size.checked_add(value))
+            .ok_or(ParseError::BodyTooLarge)?;
+    }
+    if valid_extensions(&line[digits..]) {
+        Ok(size)
+    } else {
+        Err(ParseError::InvalidChunk)
+    }
+}
+
+/// `*( BWS ";" BWS ext-name [ BWS "=" BWS ( token / quoted-string ) ] )`
+fn valid_extensions(s: &[u8]) -> bool {
+    let skip_bws = |i: &mut usize| {
+        while *i < s.len() && (s[*i] == b' ' || s[*i] == b'\t') {
+            *i += 1;
+        }
+    };
+    let skip_token = |i: &mut usize| {
+        let start = *i;
+        while *i < s.len() && is_tchar(s[*i]) {
+            *i += 1;
+        }
+        *i > start
+    };
+    let mut i = 0;
+    loop {
+        skip_bws(&mut i);
+        if i == s.len() {
+            return true;
+        }
+        if s[i] != b';' {
+            return false;
+        }
+        i += 1;
+        skip_bws(&mut i);
+        if !skip_token(&mut i) {
+            return false;
+        }
+        skip_bws(&mut i);
+        if s.get(i) != Some(&b'=') {
+            continue;
+        }
+        i += 1;
+        skip_bws(&mut i);
+        if s.get(i) == Some(&b'"') {
+            i += 1;
+            loop {
+                match s.get(i) {
+                    None => return false,
+                    Some(b'"') => break,
+                    Some(b'\\') => i += 2,
+                    Some(_) => i += 1,
+                }
+            }
+            i += 1;
+        } else if !skip_token(&mut i) {
+            return false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &[u8] = b"5;name=value\r\nhello\r\n7; a = \"x;\\\"y\" ;b\r\n, world\r\n\
+        0\r\nExpires: never\r\nX-Checksum: 42\r\n\r\nGET / HTTP/1.1";
+
+    #[test]
+    fn decodes_body_and_trailers() {
+        let mut decoder = ChunkedDecoder::new();
+        let mut body = Vec::new();
+        let consumed = decoder.decode(BODY, &mut body).unwrap();
+        assert!(decoder.is_done());
+        assert_eq!(body, b"hello, world");
+        assert_eq!(&BODY[consumed..], b"GET / HTTP/1.1");
+        assert_eq!(decoder.trailers().get("expires"), Some("never"));
+        assert_eq!(decoder.take_trailers().get("X-Checksum"), Some("42"));
+    }
+
+    #[test]
+    fn decodes_body_split_at_every_byte() {
+        let mut decoder = ChunkedDecoder::new();
+        let mut body = Vec::new();
+        let mut pending = Vec::new();
+        for byte in BODY {
+            pending.push(*byte);
+            let consumed = decoder.decode(&pending, &mut body).unwrap();
+            pending.drain(..consumed);
+        }
+        assert!(decoder.is_done());
+        assert_eq!(body, b"hello, world");
+        assert_eq!(pending, b"GET / HTTP/1.1");
+    }
+
+    #[test]
+    fn rejects_malformed_chunks() {
+        let cases: [&[u8]; 4] = [
+            b"x\r\n",
+            b"5\r\nhelloXX",
+            b"5;=oops\r\n",
+            b"5; a=\"unterminated\r\n",
+        ];
+        for input in cases.iter() {
+            let mut decoder = ChunkedDecoder::new();
+            assert_eq!(
+                decoder.decode(input, &mut Vec::new()),
+                Err(ParseError::InvalidChunk)
+            );
+        }
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let mut decoder = ChunkedDecoder::with_limits(8, 16);
+        let result = decoder.decode(b"5\r\nhello\r\n4\r\n", &mut Vec::new());
+        assert_eq!(result, Err(ParseError::BodyTooLarge));
+
+        let mut decoder = ChunkedDecoder::new();
+        let result = decoder.decode(b"fffffffffffffffffffff\r\n", &mut Vec::new());
+        assert_eq!(result, Err(ParseError::BodyTooLarge));
+
+        let mut decoder = ChunkedDecoder::new();
+        let long_line = vec![b'1'; MAX_CHUNK_LINE];
+        let result = decoder.decode(&long_line, &mut Vec::new());
+        assert_eq!(result, Err(ParseError::ChunkLineTooLong));
+
+        let mut decoder = ChunkedDecoder::with_limits(8, 16);
+        let result =
+            decoder.decode(b"0\r\nX-Long-Trailer: 0123456789\r\n", &mut Vec::new());
+        assert_eq!(result, Err(ParseError::TrailersTooLarge));
+    }
+}
@@ -1,139 +1,357 @@
 //! Create HTTP Responses that can be understood by a browser
 #![allow(non_snake_case)]
-use std::collections::HashMap;
-use std::io::Write;
+use crate::headers::HeaderMap;
+use crate::http_request::Version;
+use crate::status::StatusCode;
+use std::io::{self, IoSlice, Write};
+
+mod body;
+pub mod chunked;
+mod reader;
+pub use body::Body;
+pub use reader::ResponseReader;
+use chunked::ChunkedEncoder;
 
 /// Type representing an HTTP response
 
 // The status line can be broken down into 3 fields:
-// HTTP version, Status Code and Status text
-#[derive(Debug, PartialEq, Clone)]
-pub struct HttpResponse<'a> {
-    version: &'a str,
-    status_code: &'a str,
-    status_text: &'a str,
-    headers: Option<HashMap<&'a str, &'a str>>,
-    body: Option<String>,
+// HTTP version, Status Code and Status text. The text is the reason phrase of the
+// status code.
+#[derive(Debug, PartialEq, Default)]
+pub struct HttpResponse {
+    version: Version,
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Body,
+    /// Sent after the body, which only chunked responses can do
+    trailers: HeaderMap,
+    /// Whether only the head goes out, as in the answer to a HEAD request
+    head_only: bool,
 }
 
-impl<'a> Default for HttpResponse<'a> {
-    fn default() -> Self {
-        Self {
-            version: "HTTP/1.1",
-            status_code: "200",
-            status_text: "OK",
-            headers: None,
-            body: None,
-        }
+impl From<HttpResponse> for String {
+    // Serialize HttpResponse into a String. Meant for text responses, a binary body
+    // gets mangled, and a streamed body that fails to read is cut short.
+    fn from(hrp: HttpResponse) -> String {
+        let mut res = Vec::new();
+        let _ = hrp.send_response(&mut res);
+        String::from_utf8_lossy(&res).into_owned()
     }
 }
 
-impl<'a> From<HttpResponse<'a>> for String {
-    // Serialize HttpResponse into a String for transmission
-    fn from(hrp: HttpResponse<'a>) -> String {
-        let mut res = String::new();
-        res.push_str(&format!(
-            "{} {} {}",
-            hrp.version, hrp.status_code, hrp.status_text
-        ));
-        let mut content_length_defined: bool = false;
-        match hrp.headers {
-            None => (),
-            Some(hashmap) => {
-                for (k, v) in hashmap {
-                    // Insert one ugly check for Content-length so that it may not
-                    // be repeated in the match hrp.body code block:
-                    if let Some(_) = k.find("Content-length") {
-                        content_length_defined = true;
-                    }
-                    let line = k.to_string() + ": " + v;
-                    // for some reason r#"\r\n" pushes double slashes like : \\r\\n
-                    res.push_str("\r\n");
-                    res.push_str(&line);
-                }
+impl HttpResponse {
+    /// Start building a response, by default an empty `200 OK` over HTTP/1.1
+    pub fn builder() -> ResponseBuilder {
+        ResponseBuilder::default()
+    }
+
+    /// Shorthand for the builder: a response with `status`, the given headers and
+    /// body, and a Content-type of text/html unless the headers say otherwise
+    pub fn new(status: StatusCode, headers: Option<HeaderMap>, body: Option<String>) -> Self {
+        let mut headers = headers.unwrap_or_default();
+        // Check if headers contains Content-type
+        // if not, insert it
+        if !headers.contains("Content-type") {
+            headers.insert("Content-type", "text/html");
+        }
+        let builder = ResponseBuilder {
+            response: HttpResponse {
+                headers,
+                ..Self::default()
+            },
+        }
+        .status(status);
+        match body {
+            Some(body) => builder.body(body),
+            None => builder.build(),
+        }
+    }
+
+    /// A response put together by `parser::ResponseParser`
+    pub(crate) fn from_parts(
+        version: Version,
+        status: StatusCode,
+        headers: HeaderMap,
+        body: Vec<u8>,
+        trailers: HeaderMap,
+    ) -> Self {
+        HttpResponse {
+            version,
+            status,
+            headers,
+            body: Body::Bytes(body),
+            trailers,
+            head_only: false,
+        }
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// Answer in `version`, typically that of the request. This also decides whether
+    /// a body of unknown length can be chunked, which HTTP/1.0 peers don't support.
+    pub fn set_version(&mut self, version: Version) {
+        self.version = version;
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    pub fn headers_mut(&mut self) -> &mut HeaderMap {
+        &mut self.headers
+    }
+
+    pub fn body(&self) -> &Body {
+        &self.body
+    }
+
+    pub fn trailers(&self) -> &HeaderMap {
+        &self.trailers
+    }
+
+    pub fn trailers_mut(&mut self) -> &mut HeaderMap {
+        &mut self.trailers
+    }
+
+    /// Send the head only, the way a HEAD request is answered. The headers stay
+    /// what they would have been with the body, Content-length included.
+    pub fn omit_body(&mut self) {
+        self.head_only = true;
+    }
+
+    /// Write the response to a `Write` data type, streaming the body rather than
+    /// loading it into memory. A body of unknown length is sent chunked to HTTP/1.1
+    /// peers and delimited by closing the connection otherwise.
+    ///
+    /// The head and an in-memory body go out in one `write_vectored` call straight
+    /// from where they are stored, without being copied into a buffer first.
+    pub fn send_response(self, writer: &mut impl Write) -> io::Result<()> {
+        let chunked = self.is_chunked();
+        let framing = self.framing(chunked);
+        {
+            let digits = self.status_digits();
+            let mut slices = self.head(&digits, &framing);
+            if let Some(body) = self.body.as_bytes().filter(|_| self.sends_body()) {
+                slices.push(IoSlice::new(body));
             }
+            write_all_vectored(writer, &mut slices)?;
+        }
+        if !self.sends_body() {
+            return writer.flush();
         }
-        match hrp.body {
-            None => (),
-            Some(body) => {
-                // There's a risk of double inserting Content-length here:
-                // hrp.headers may already contain this line
-                if !content_length_defined {
-                    res.push_str(&format!("\r\nContent-length: {}", body.len()));
-                }
-                res.push_str("\r\n\r\n");
-                res.push_str(&body);
+        if self.body.as_bytes().is_none() {
+            if chunked {
+                let mut encoder = ChunkedEncoder::new(&mut *writer);
+                self.body.write_to(&mut encoder)?;
+                encoder.finish(&self.trailers)?;
+            } else {
+                self.body.write_to(writer)?;
             }
         }
-        res
+        writer.flush()
     }
-}
 
-impl<'a> HttpResponse<'a> {
-    /// Accepts custom values for a HTTP response and edits them into the default value
-    /// as returned by `HttpResponse::default()`
-    pub fn new(
-        status_code: &'a str,
-        headers: Option<HashMap<&'a str, &'a str>>,
-        body: Option<String>,
-    ) -> Self {
-        // todo!("Add a new field to set HTTP version");
-        let mut response = Self::default();
-        if status_code != response.status_code {
-            response.status_code = status_code;
+    /// Turn the response into the bytes `send_response` would write, to be read a
+    /// piece at a time. This suits a non-blocking socket, which can only take as
+    /// much as fits in its buffer before the rest has to wait.
+    pub fn into_reader(self) -> ResponseReader {
+        let chunked = self.is_chunked();
+        let framing = self.framing(chunked);
+        let mut head = Vec::new();
+        {
+            let digits = self.status_digits();
+            for slice in self.head(&digits, &framing) {
+                head.extend_from_slice(&slice);
+            }
         }
-        response.status_text = match status_code {
-            // you can add more status codes here
-            "200" => "OK",
-            "400" => "Bad Request",
-            "404" => "Not Found",
-            "500" => "Internal Server Error",
-            _ => "Lol",
-        };
-        response.headers = match headers {
-            Some(mut headers) => {
-                // Check if headers contains Content-type
-                // if not, insert it
-                headers.entry("Content-type").or_insert("text/html");
-                Some(headers)
+        if !self.sends_body() {
+            return ResponseReader::new(head, Body::Empty, None);
+        }
+        let trailers = if chunked { Some(self.trailers) } else { None };
+        ResponseReader::new(head, self.body, trailers)
+    }
+
+    /// The status code as the three digits of the status line
+    fn status_digits(&self) -> [u8; 3] {
+        let code = self.status.as_u16();
+        [
+            b'0' + (code / 100) as u8,
+            b'0' + (code / 10 % 10) as u8,
+            b'0' + (code % 10) as u8,
+        ]
+    }
+
+    /// The status line and header section, ending with the blank line
+    fn head<'a>(&'a self, digits: &'a [u8; 3], framing: &'a str) -> Vec<IoSlice<'a>> {
+        let mut slices = Vec::with_capacity(8 + 4 * self.headers.len());
+        slices.push(IoSlice::new(self.version.as_str().as_bytes()));
+        slices.push(IoSlice::new(b" "));
+        slices.push(IoSlice::new(digits));
+        slices.push(IoSlice::new(b" "));
+        slices.push(IoSlice::new(self.status.reason().as_bytes()));
+        slices.push(IoSlice::new(b"\r\n"));
+        for (name, value) in self.headers.iter() {
+            slices.push(IoSlice::new(name.as_bytes()));
+            slices.push(IoSlice::new(b": "));
+            slices.push(IoSlice::new(value.as_bytes()));
+            slices.push(IoSlice::new(b"\r\n"));
+        }
+        slices.push(IoSlice::new(framing.as_bytes()));
+        slices.push(IoSlice::new(b"\r\n"));
+        slices
+    }
+
+    /// Whether the only way to tell the client where the body ends is to close the
+    /// connection after it: a body of unknown length that can't be sent chunked
+    pub fn is_close_delimited(&self) -> bool {
+        self.sends_body()
+            && self.body.len().is_none()
+            && !self.is_chunked()
+            && !self.headers.contains("Content-length")
+    }
+
+    /// Whether the body goes out with chunked framing
+    fn is_chunked(&self) -> bool {
+        self.body.len().is_none()
+            && self.version == Version::V1_1
+            && self.allows_body()
+            && !self.headers.contains("Content-length")
+    }
+
+    /// The header lines that say where the body ends, if any are needed
+    fn framing(&self, chunked: bool) -> String {
+        // There's a risk of double inserting Content-length here:
+        // the headers may already contain this line
+        if let Some(length) = self.body.len() {
+            if !self.headers.contains("Content-length") && self.allows_body() {
+                return format!("Content-length: {}\r\n", length);
             }
-            None => {
-                let mut h: HashMap<&'a str, &'a str> = HashMap::new();
-                h.insert("Content-type", "text/html");
-                Some(h)
+        } else if chunked {
+            let mut framing = String::from("Transfer-Encoding: chunked\r\n");
+            // Announce the trailers so the client knows to look out for them
+            if !self.trailers.is_empty() && !self.headers.contains("Trailer") {
+                let names: Vec<&str> = self.trailers.iter().map(|(name, _)| name).collect();
+                framing.push_str(&format!("Trailer: {}\r\n", names.join(", ")));
             }
-        };
-        response.body = match body {
-            Some(_) => body,
-            None => None,
-        };
-        response
+            return framing;
+        }
+        String::new()
     }
 
-    /// Write the current HttpResponse object to a `Write` data type
-    pub fn send_response(&self, writer: &mut impl Write) -> std::io::Result<()> {
-        let res_clone = self.clone();
-        let res = String::from(res_clone);
-        // let bytes_written = writer.write(res.as_bytes())?;
-        write!(writer, "{}", res)
+    /// Whether the body goes out after the head at all
+    fn sends_body(&self) -> bool {
+        !self.head_only && self.allows_body()
     }
+
+    /// 1xx, 204 and 304 responses never carry a body (RFC 9110, section 6.4.1)
+    fn allows_body(&self) -> bool {
+        !self.status.is_informational()
+            && self.status != StatusCode::NO_CONTENT
+            && self.status != StatusCode::NOT_MODIFIED
+    }
+}
+
+/// Write every byte of `slices`, retrying short writes. This is what the unstable
+/// `Write::write_all_vectored` does.
+pub(crate) fn write_all_vectored(
+    writer: &mut impl Write,
+    mut slices: &mut [IoSlice<'_>],
+) -> io::Result<()> {
+    // Drop leading empty slices so that writing nothing means the writer is stuck
+    IoSlice::advance_slices(&mut slices, 0);
+    while !slices.is_empty() {
+        match writer.write_vectored(slices) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "failed to write whole response",
+                ))
+            }
+            Ok(written) => IoSlice::advance_slices(&mut slices, written),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
 }
 
+/// Assembles an `HttpResponse` one part at a time:
+///
+/// ```
+/// use http::http_response::HttpResponse;
+/// use http::status::StatusCode;
+///
+/// let response = HttpResponse::builder()
+///     .status(StatusCode::CREATED)
+///     .header("Location", "/orders/42")
+///     .body("created");
+/// assert_eq!(response.status(), StatusCode::CREATED);
+/// ```
+#[derive(Debug, Default)]
+pub struct ResponseBuilder {
+    response: HttpResponse,
+}
+
+impl ResponseBuilder {
+    pub fn status(mut self, status: StatusCode) -> Self {
+        self.response.status = status;
+        self
+    }
+
+    pub fn version(mut self, version: Version) -> Self {
+        self.response.version = version;
+        self
+    }
+
+    /// Add a header field, keeping any earlier values of the same name
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.response.headers.append(name, value);
+        self
+    }
+
+    /// Add a trailer field, sent after the body when it ends up chunked
+    pub fn trailer(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.response.trailers.append(name, value);
+        self
+    }
+
+    /// Finish the response with `body`
+    pub fn body(mut self, body: impl Into<Body>) -> HttpResponse {
+        self.response.body = body.into();
+        self.response
+    }
+
+    /// Finish the response without a body
+    pub fn build(self) -> HttpResponse {
+        self.response
+    }
+}
+
+#[cfg(test)]
 mod tests {
     use super::*;
+    use std::io::Read;
     #[test]
     fn check_status_200_OK() {
-        let response = HttpResponse::new("200", None, Some("lorem ipsum".into()));
+        let response =
+            HttpResponse::new(StatusCode::OK, None, Some("lorem ipsum".into()));
         let expected = HttpResponse {
-            version: "HTTP/1.1",
-            status_code: "200",
-            status_text: "OK",
+            version: Version::V1_1,
+            status: StatusCode::OK,
             headers: {
-                let mut h: HashMap<&str, &str> = HashMap::new();
+                let mut h = HeaderMap::new();
                 h.insert("Content-type", "text/html");
-                Some(h)
+                h
             },
-            body: Some(String::from("lorem ipsum")),
+            body: Body::from("lorem ipsum"),
+            trailers: HeaderMap::new(),
+            head_only: false,
         };
         assert_eq!(response, expected);
     }
@@ -141,26 +359,27 @@ mod tests {
     fn check_double_content_length_defined() {
         let body = String::from("lorem ipsum");
         let body_len = &body.len().to_string()[..];
-        let mut headers: HashMap<&str, &str> = HashMap::new();
+        let mut headers = HeaderMap::new();
 
-        headers.entry("Content-length").or_insert(body_len);
+        headers.insert("Content-length", body_len);
         headers.insert("Content-type", "text/html");
 
         let response =
-            HttpResponse::new("200", Some(headers), Some("lorem ipsum".into()));
+            HttpResponse::new(StatusCode::OK, Some(headers), Some("lorem ipsum".into()));
         let expected = HttpResponse {
-            version: "HTTP/1.1",
-            status_code: "200",
-            status_text: "OK",
+            version: Version::V1_1,
+            status: StatusCode::OK,
             headers: {
-                let mut h: HashMap<&str, &str> = HashMap::new();
+                let mut h = HeaderMap::new();
                 h.insert("Content-type", "text/html");
                 // headers has a Content-length defined so the body block in
                 // `From<HttpResponse> for String` should not trigger
                 h.insert("Content-length", body_len);
-                Some(h)
+                h
             },
-            body: Some(body),
+            body: Body::from(body),
+            trailers: HeaderMap::new(),
+            head_only: false,
         };
         assert_eq!(response, expected);
     }
@@ -168,9 +387,9 @@ mod tests {
     fn check_serialization_http_response() {
         let body = "It's a nice day today";
         let response = HttpResponse::new(
-            "500",
+            StatusCode::INTERNAL_SERVER_ERROR,
             {
-                let mut h = HashMap::new();
+                let mut h = HeaderMap::new();
                 h.insert("Content-type", "text/html");
                 Some(h)
             },
@@ -181,4 +400,144 @@ mod tests {
         let expected = format!("HTTP/1.1 500 Internal Server Error\r\nContent-type: text/html\r\nContent-length: {}\r\n\r\n{}", body.len(), body);
         assert_eq!(response_str, expected);
     }
+    #[test]
+    fn check_repeated_headers_serialized_in_order() {
+        let mut headers = HeaderMap::new();
+        headers.append("Set-Cookie", "a=1");
+        headers.append("Set-Cookie", "b=2");
+        let response = HttpResponse::new(StatusCode::OK, Some(headers), Some("ok".into()));
+
+        let expected = "HTTP/1.1 200 OK\r\nSet-Cookie: a=1\r\nSet-Cookie: b=2\r\n\
+            Content-type: text/html\r\nContent-length: 2\r\n\r\nok";
+        assert_eq!(String::from(response), expected);
+    }
+    #[test]
+    fn builder_sets_every_part() {
+        let response = HttpResponse::builder()
+            .status(StatusCode::SEE_OTHER)
+            .version(Version::V1_0)
+            .header("Location", "/orders")
+            .header("Set-Cookie", "a=1")
+            .body("moved");
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(response.headers().get("location"), Some("/orders"));
+        assert_eq!(response.body().as_bytes(), Some(&b"moved"[..]));
+
+        let expected = "HTTP/1.0 303 See Other\r\nLocation: /orders\r\nSet-Cookie: a=1\r\n\
+            Content-length: 5\r\n\r\nmoved";
+        assert_eq!(String::from(response), expected);
+    }
+    #[test]
+    fn unregistered_status_uses_class_reason() {
+        let status = StatusCode::from_u16(499).unwrap();
+        let response = HttpResponse::builder().status(status).build();
+        assert_eq!(
+            String::from(response),
+            "HTTP/1.1 499 Client Error\r\nContent-length: 0\r\n\r\n"
+        );
+    }
+    #[test]
+    fn sends_binary_and_streamed_bodies() {
+        let png = vec![0x89, b'P', b'N', b'G', 0x00, 0xff];
+        let mut out = Vec::new();
+        HttpResponse::builder()
+            .header("Content-type", "image/png")
+            .body(png.clone())
+            .send_response(&mut out)
+            .unwrap();
+        let head = b"HTTP/1.1 200 OK\r\nContent-type: image/png\r\nContent-length: 6\r\n\r\n";
+        assert_eq!(out, [&head[..], &png].concat());
+
+        let mut out = Vec::new();
+        HttpResponse::builder()
+            .body(Body::from_reader(&b"streamed"[..], Some(8)))
+            .send_response(&mut out)
+            .unwrap();
+        assert_eq!(out, b"HTTP/1.1 200 OK\r\nContent-length: 8\r\n\r\nstreamed");
+    }
+    #[test]
+    fn bodiless_statuses_get_no_content_length() {
+        let response = HttpResponse::builder().status(StatusCode::NO_CONTENT).build();
+        assert_eq!(String::from(response), "HTTP/1.1 204 No Content\r\n\r\n");
+    }
+    #[test]
+    fn bodiless_statuses_drop_their_body() {
+        let response = HttpResponse::builder()
+            .status(StatusCode::NO_CONTENT)
+            .body("stray");
+        assert_eq!(String::from(response), "HTTP/1.1 204 No Content\r\n\r\n");
+
+        let mut response = HttpResponse::builder()
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Body::from_reader(&b"stray"[..], None));
+        response.set_version(Version::V1_0);
+        assert!(!response.is_close_delimited());
+        let mut out = String::new();
+        response.into_reader().read_to_string(&mut out).unwrap();
+        assert_eq!(out, "HTTP/1.0 304 Not Modified\r\n\r\n");
+    }
+    #[test]
+    fn streams_of_unknown_length_are_chunked_for_http_1_1() {
+        let mut out = Vec::new();
+        HttpResponse::builder()
+            .trailer("X-Checksum", "42")
+            .body(Body::from_reader(&b"streamed"[..], None))
+            .send_response(&mut out)
+            .unwrap();
+        let expected = "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nTrailer: X-Checksum\r\n\
+            \r\n8\r\nstreamed\r\n0\r\nX-Checksum: 42\r\n\r\n";
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+
+        let mut response = HttpResponse::builder()
+            .trailer("X-Checksum", "42")
+            .body(Body::from_reader(&b"streamed"[..], None));
+        response.set_version(Version::V1_0);
+        assert!(response.is_close_delimited());
+        assert_eq!(String::from(response), "HTTP/1.0 200 OK\r\n\r\nstreamed");
+    }
+    #[test]
+    fn omitted_bodies_keep_their_framing_headers() {
+        let mut response = HttpResponse::builder().body("hello");
+        response.omit_body();
+        let expected = "HTTP/1.1 200 OK\r\nContent-length: 5\r\n\r\n";
+        assert_eq!(String::from(response), expected);
+
+        let mut response =
+            HttpResponse::builder().body(Body::from_reader(&b"streamed"[..], None));
+        response.omit_body();
+        let mut out = String::new();
+        response.into_reader().read_to_string(&mut out).unwrap();
+        assert_eq!(out, "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n");
+
+        let mut response =
+            HttpResponse::builder().body(Body::from_reader(&b"streamed"[..], None));
+        response.set_version(Version::V1_0);
+        response.omit_body();
+        assert!(!response.is_close_delimited());
+        assert_eq!(String::from(response), "HTTP/1.0 200 OK\r\n\r\n");
+    }
+    #[test]
+    fn survives_short_writes() {
+        // Takes at most 3 bytes per call, like a congested socket
+        struct Trickle(Vec<u8>);
+        impl Write for Trickle {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                let n = buf.len().min(3);
+                self.0.extend_from_slice(&buf[..n]);
+                Ok(n)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        let mut out = Trickle(Vec::new());
+        HttpResponse::builder()
+            .header("Content-type", "text/plain")
+            .body("hello")
+            .send_response(&mut out)
+            .unwrap();
+        let expected = "HTTP/1.1 200 OK\r\nContent-type: text/plain\r\nContent-length: 5\r\n\
+            \r\nhello";
+        assert_eq!(String::from_utf8(out.0).unwrap(), expected);
+    }
 }
@@ -0,0 +1,167 @@
+//! Response bodies
+//!
+//! A body is either held in memory or read from a source such as a file while it is
+//! being sent, so that large downloads never have to fit in memory.
+use std::fmt;
+use std::io::{self, Read, Write};
+
+/// The payload of an `HttpResponse`
+#[derive(Default)]
+pub enum Body {
+    /// No payload at all
+    #[default]
+    Empty,
+    /// A payload held in memory
+    Bytes(Vec<u8>),
+    /// A payload read from `reader` as it is sent. `length` is the number of bytes
+    /// it will produce, when known up front.
+    Reader {
+        reader: Box<dyn Read + Send>,
+        length: Option<u64>,
+    },
+}
+
+impl Body {
+    pub fn empty() -> Self {
+        Body::Empty
+    }
+
+    /// A body streamed from `reader`. Pass the `length` if it is known, a file's
+    /// size for instance; the reader must then produce exactly that many bytes.
+    pub fn from_reader(reader: impl Read + Send + 'static, length: Option<u64>) -> Self {
+        Body::Reader {
+            reader: Box::new(reader),
+            length,
+        }
+    }
+
+    /// Number of bytes in the body, or `None` for a stream of unknown length
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            Body::Empty => Some(0),
+            Body::Bytes(bytes) => Some(bytes.len() as u64),
+            Body::Reader { length, .. } => *length,
+        }
+    }
+
+    /// Whether the body is known to be empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    /// The payload, if it is held in memory
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Body::Empty => Some(&[]),
+            Body::Bytes(bytes) => Some(bytes),
+            Body::Reader { .. } => None,
+        }
+    }
+
+    /// Copy the payload to `writer`, returning the number of bytes written. Fails if
+    /// a reader ends before its declared length.
+    pub fn write_to(self, writer: &mut impl Write) -> io::Result<u64> {
+        match self {
+            Body::Empty => Ok(0),
+            Body::Bytes(bytes) => {
+                writer.write_all(&bytes)?;
+                Ok(bytes.len() as u64)
+            }
+            Body::Reader {
+                reader,
+                length: Some(length),
+            } => {
+                let copied = io::copy(&mut reader.take(length), writer)?;
+                if copied < length {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "response body shorter than its length",
+                    ));
+                }
+                Ok(copied)
+            }
+            Body::Reader {
+                mut reader,
+                length: None,
+            } => io::copy(&mut reader, writer),
+        }
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Body::Empty => f.write_str("Empty"),
+            Body::Bytes(bytes) => f.debug_tuple("Bytes").field(&bytes.len()).finish(),
+            Body::Reader { length, .. } => {
+                f.debug_struct("Reader").field("length", length).finish()
+            }
+        }
+    }
+}
+
+/// Bodies held in memory compare by content. A stream can't be inspected without
+/// consuming it, so a `Reader` body is never equal to anything.
+impl PartialEq for Body {
+    fn eq(&self, other: &Self) -> bool {
+        match (self.as_bytes(), other.as_bytes()) {
+            (Some(a), Some(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Self {
+        Body::Bytes(bytes)
+    }
+}
+
+impl From<&[u8]> for Body {
+    fn from(bytes: &[u8]) -> Self {
+        Body::Bytes(bytes.to_vec())
+    }
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Self {
+        Body::Bytes(text.into_bytes())
+    }
+}
+
+impl From<&str> for Body {
+    fn from(text: &str) -> Self {
+        Body::Bytes(text.as_bytes().to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn streams_readers() {
+        let body = Body::from_reader(&b"hello world"[..], Some(5));
+        assert_eq!(body.len(), Some(5));
+        let mut out = Vec::new();
+        assert_eq!(body.write_to(&mut out).unwrap(), 5);
+        assert_eq!(out, b"hello");
+
+        let mut out = Vec::new();
+        Body::from_reader(&b"hello"[..], None)
+            .write_to(&mut out)
+            .unwrap();
+        assert_eq!(out, b"hello");
+
+        let short = Body::from_reader(&b"hi"[..], Some(5));
+        let err = short.write_to(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn compares_bodies_in_memory() {
+        assert_eq!(Body::from("abc"), Body::from(vec![b'a', b'b', b'c']));
+        assert_eq!(Body::empty(), Body::from(""));
+        assert_ne!(Body::from_reader(&b""[..], Some(0)), Body::empty());
+    }
+}
@@ -0,0 +1,90 @@
+//! Encoding of response bodies with `Transfer-Encoding: chunked`
+//!
+//! Chunked framing lets a response start before its length is known: every write
+//! becomes one chunk, and a zero sized chunk followed by optional trailer fields
+//! marks the end (RFC 9112, section 7.1). The decoding side lives in
+//! `http_request::chunked`.
+use crate::headers::HeaderMap;
+use crate::http_response::write_all_vectored;
+use std::io::{self, IoSlice, Write};
+
+/// Writes everything written to it as chunks on the inner writer. Call `finish` to
+/// end the body, dropping the encoder leaves it unterminated.
+#[derive(Debug)]
+pub struct ChunkedEncoder<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> ChunkedEncoder<W> {
+    pub fn new(inner: W) -> Self {
+        ChunkedEncoder { inner }
+    }
+
+    /// The writer the chunks go to
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Write the last chunk and the `trailers`, and hand back the inner writer
+    pub fn finish(mut self, trailers: &HeaderMap) -> io::Result<W> {
+        let mut end = String::from("0\r\n");
+        for (name, value) in trailers.iter() {
+            end.push_str(name);
+            end.push_str(": ");
+            end.push_str(value);
+            end.push_str("\r\n");
+        }
+        end.push_str("\r\n");
+        self.inner.write_all(end.as_bytes())?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ChunkedEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // An empty chunk would end the body
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let size = format!("{:X}\r\n", buf.len());
+        let mut chunk = [
+            IoSlice::new(size.as_bytes()),
+            IoSlice::new(buf),
+            IoSlice::new(b"\r\n"),
+        ];
+        write_all_vectored(&mut self.inner, &mut chunk)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_request::chunked::ChunkedDecoder;
+
+    #[test]
+    fn encodes_chunks_and_trailers() {
+        let mut encoder = ChunkedEncoder::new(Vec::new());
+        encoder.write_all(b"hello").unwrap();
+        encoder.write_all(b"").unwrap();
+        encoder.write_all(b", chunked world").unwrap();
+        let trailers: HeaderMap = vec![("X-Checksum", "42")].into_iter().collect();
+        let encoded = encoder.finish(&trailers).unwrap();
+        assert_eq!(
+            encoded,
+            b"5\r\nhello\r\nF\r\n, chunked world\r\n0\r\nX-Checksum: 42\r\n\r\n".to_vec()
+        );
+
+        let mut decoder = ChunkedDecoder::new();
+        let mut body = Vec::new();
+        assert_eq!(decoder.decode(&encoded, &mut body), Ok(encoded.len()));
+        assert!(decoder.is_done());
+        assert_eq!(body, b"hello, chunked world");
+        assert_eq!(decoder.trailers(), &trailers);
+    }
+}
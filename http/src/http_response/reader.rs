@@ -0,0 +1,186 @@
+//! Responses that are read rather than written
+//!
+//! `HttpResponse::send_response` pushes the whole response into a writer, which
+//! only works if the writer can block until the peer catches up. `ResponseReader`
+//! turns it around: the response is pulled out as the connection is ready for more,
+//! and a streamed body is only read from its source a piece at a time.
+use super::chunked::ChunkedEncoder;
+use super::Body;
+use crate::headers::HeaderMap;
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+
+/// How much of a streamed body is read from its source at a time
+const PIECE_SIZE: usize = 16 * 1024;
+
+/// A serialized response, made by `HttpResponse::into_reader`
+pub struct ResponseReader {
+    /// Bytes waiting to be read, starting at `pos`
+    ready: Vec<u8>,
+    pos: usize,
+    /// The part of a streamed body that hasn't been read yet
+    stream: Option<Box<dyn Read + Send>>,
+    /// How much the stream still owes, if its length was given
+    remaining: Option<u64>,
+    /// The encoder and trailers of a chunked body
+    chunked: Option<(ChunkedEncoder<Vec<u8>>, HeaderMap)>,
+}
+
+impl ResponseReader {
+    /// `head` is the status line and header section; `trailers` is `Some` for a
+    /// chunked body
+    pub(super) fn new(
+        mut head: Vec<u8>,
+        body: Body,
+        trailers: Option<HeaderMap>,
+    ) -> Self {
+        let (stream, remaining) = match body {
+            Body::Empty => (None, None),
+            Body::Bytes(bytes) => {
+                head.extend_from_slice(&bytes);
+                (None, None)
+            }
+            Body::Reader { reader, length } => (Some(reader), length),
+        };
+        ResponseReader {
+            ready: head,
+            pos: 0,
+            stream,
+            remaining,
+            chunked: trailers.map(|trailers| (ChunkedEncoder::new(Vec::new()), trailers)),
+        }
+    }
+
+    /// Read the next piece of the body into `ready`. Returns false once there is
+    /// nothing left.
+    fn refill(&mut self) -> io::Result<bool> {
+        self.ready.clear();
+        self.pos = 0;
+        let stream = match &mut self.stream {
+            Some(stream) => stream,
+            None => return Ok(false),
+        };
+        let mut buf = [0_u8; PIECE_SIZE];
+        let want = match self.remaining {
+            Some(remaining) => remaining.min(PIECE_SIZE as u64) as usize,
+            None => PIECE_SIZE,
+        };
+        let bytes_read = loop {
+            match stream.read(&mut buf[..want]) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                result => break result?,
+            }
+        };
+        if bytes_read == 0 {
+            self.stream = None;
+            if self.remaining.is_some_and(|remaining| remaining > 0) {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "response body shorter than its length",
+                ));
+            }
+            return match self.chunked.take() {
+                Some((encoder, trailers)) => {
+                    self.ready = encoder.finish(&trailers)?;
+                    Ok(true)
+                }
+                None => Ok(false),
+            };
+        }
+        if let Some(remaining) = &mut self.remaining {
+            *remaining -= bytes_read as u64;
+        }
+        match &mut self.chunked {
+            Some((encoder, _)) => {
+                encoder.write_all(&buf[..bytes_read])?;
+                std::mem::swap(&mut self.ready, encoder.get_mut());
+            }
+            None => self.ready.extend_from_slice(&buf[..bytes_read]),
+        }
+        Ok(true)
+    }
+}
+
+impl Read for ResponseReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+/// Lets the caller write straight from the reader's buffer without copying
+impl BufRead for ResponseReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.pos == self.ready.len() {
+            if !self.refill()? {
+                break;
+            }
+        }
+        Ok(&self.ready[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.ready.len());
+    }
+}
+
+impl fmt::Debug for ResponseReader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseReader")
+            .field("ready", &(self.ready.len() - self.pos))
+            .field("streaming", &self.stream.is_some())
+            .field("chunked", &self.chunked.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http_request::Version;
+    use crate::http_response::{Body, HttpResponse};
+
+    fn responses() -> Vec<HttpResponse> {
+        let long = "x".repeat(40_000);
+        let mut close_delimited = HttpResponse::builder()
+            .body(Body::from_reader(std::io::Cursor::new(long.clone()), None));
+        close_delimited.set_version(Version::V1_0);
+        vec![
+            HttpResponse::builder()
+                .header("Content-type", "text/plain")
+                .body("hi"),
+            HttpResponse::builder()
+                .body(Body::from_reader(std::io::Cursor::new(long), Some(40_000))),
+            // Short enough to become a single chunk either way
+            HttpResponse::builder()
+                .trailer("X-Checksum", "42")
+                .body(Body::from_reader(&b"streamed"[..], None)),
+            close_delimited,
+        ]
+    }
+
+    #[test]
+    fn reads_what_send_response_writes() {
+        for (sent, read) in responses().into_iter().zip(responses()) {
+            let mut expected = Vec::new();
+            sent.send_response(&mut expected).unwrap();
+            let mut actual = Vec::new();
+            std::io::copy(&mut read.into_reader(), &mut actual).unwrap();
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn fails_when_a_stream_is_short() {
+        let response =
+            HttpResponse::builder().body(Body::from_reader(&b"short"[..], Some(10)));
+        let mut out = Vec::new();
+        let result = std::io::copy(&mut response.into_reader(), &mut out);
+        assert_eq!(
+            result.unwrap_err().kind(),
+            std::io::ErrorKind::UnexpectedEof
+        );
+    }
+}
@@ -4,5 +4,10 @@
 //! 
 //! An HTTP Request consists of HTTP method, HTTP version, and URI 
 
+pub mod client;
+pub mod headers;
 pub mod http_request;
-pub mod http_response;
\ No newline at end of file
+pub mod http_response;
+pub mod parser;
+pub mod status;
+pub mod uri;
\ No newline at end of file
@@ -0,0 +1,757 @@
+//! Incremental parsing of HTTP requests that arrive in pieces
+//!
+//! A request read off a socket rarely shows up in a single `read`. `RequestParser`
+//! is a small state machine that buffers whatever it has been fed so far and picks
+//! up where it left off when the next chunk arrives. Once a request is complete it
+//! is handed back together with any bytes that belong to the next request on the
+//! same connection.
+use crate::headers::HeaderMap;
+use crate::http_request::chunked::{ChunkedDecoder, DEFAULT_MAX_BODY_SIZE};
+use crate::http_request::{HttpRequest, Method, Version};
+use crate::uri::{TargetForm, Uri};
+use std::fmt;
+
+mod response;
+pub use response::ResponseParser;
+
+/// Outcome of feeding a chunk of bytes to a `RequestParser`, or a `ResponseParser`
+/// when `T` is `HttpResponse`
+// Short-lived, so boxing the request isn't worth it
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+pub enum ParseStatus<T = HttpRequest> {
+    /// The message isn't complete yet, feed more bytes
+    Incomplete,
+    /// A whole message was parsed. The `Vec<u8>` holds any bytes received past
+    /// the end of this message, which belong to the next one on the connection
+    Complete(T, Vec<u8>),
+}
+
+/// Reasons a byte stream can't be turned into an `HttpRequest`
+#[derive(Debug, PartialEq, Clone)]
+pub enum ParseError {
+    /// The request line isn't of the form `METHOD target HTTP/x.y`
+    MalformedRequestLine,
+    /// The status line isn't of the form `HTTP/x.y code reason`
+    MalformedStatusLine,
+    /// The method contains characters that aren't allowed in a token
+    InvalidMethod,
+    /// The request target isn't a valid URI or doesn't suit the method
+    InvalidTarget,
+    /// A well formed HTTP version this parser doesn't speak, such as HTTP/2
+    UnsupportedVersion,
+    /// A status code isn't three digits between 100 and 599
+    InvalidStatusCode,
+    /// A header line has no colon or its name isn't a valid token
+    InvalidHeader,
+    /// The request line, a header or a text body isn't valid UTF-8
+    InvalidUtf8,
+    /// A text body is declared to be in a charset other than UTF-8
+    UnsupportedCharset,
+    /// The Content-Length header isn't a plain decimal number
+    InvalidContentLength,
+    /// Several Content-Length values that don't agree
+    ConflictingContentLength,
+    /// Both Content-Length and Transfer-Encoding are present
+    ContentLengthWithTransferEncoding,
+    /// A header line starts with whitespace, continuing the previous one
+    ObsoleteLineFolding,
+    /// There is whitespace between a header name and its colon
+    WhitespaceBeforeColon,
+    /// A line ends in LF without the CR before it
+    BareLf,
+    /// The body uses a transfer coding other than chunked
+    UnsupportedTransferEncoding,
+    /// A chunk size line is malformed or a chunk isn't followed by CRLF
+    InvalidChunk,
+    /// A chunk size line, extensions included, is unreasonably long
+    ChunkLineTooLong,
+    /// The request line is longer than `ParserLimits::max_request_line`
+    RequestLineTooLong,
+    /// More header fields than `ParserLimits::max_headers`
+    TooManyHeaders,
+    /// The header section is larger than `ParserLimits::max_header_section`
+    HeaderSectionTooLarge,
+    /// The body is larger than the parser is willing to accept
+    BodyTooLarge,
+    /// The trailer section of a chunked body is larger than allowed
+    TrailersTooLarge,
+    /// The input ended before the message was complete
+    Incomplete,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cause = match self {
+            ParseError::MalformedRequestLine => "malformed request line",
+            ParseError::MalformedStatusLine => "malformed status line",
+            ParseError::InvalidMethod => "invalid method token",
+            ParseError::InvalidTarget => "invalid request target",
+            ParseError::UnsupportedVersion => "unsupported HTTP version",
+            ParseError::InvalidStatusCode => "invalid status code",
+            ParseError::InvalidHeader => "malformed header line",
+            ParseError::InvalidUtf8 => "request is not valid UTF-8",
+            ParseError::UnsupportedCharset => "unsupported charset",
+            ParseError::InvalidContentLength => "invalid Content-Length",
+            ParseError::ConflictingContentLength => "conflicting Content-Length values",
+            ParseError::ContentLengthWithTransferEncoding => {
+                "both Content-Length and Transfer-Encoding present"
+            }
+            ParseError::ObsoleteLineFolding => "obsolete line folding",
+            ParseError::WhitespaceBeforeColon => "whitespace before header colon",
+            ParseError::BareLf => "line ending without CR",
+            ParseError::UnsupportedTransferEncoding => "unsupported Transfer-Encoding",
+            ParseError::InvalidChunk => "malformed chunk in chunked body",
+            ParseError::ChunkLineTooLong => "chunk size line too long",
+            ParseError::RequestLineTooLong => "request line too long",
+            ParseError::TooManyHeaders => "too many header fields",
+            ParseError::HeaderSectionTooLarge => "header section too large",
+            ParseError::BodyTooLarge => "body too large",
+            ParseError::TrailersTooLarge => "trailer section too large",
+            ParseError::Incomplete => "message ended prematurely",
+        };
+        f.write_str(cause)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// How forgiving the parser is towards messages that bend the rules of RFC 9112
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ParseMode {
+    /// Reject anything that a proxy in front of us might read differently: bare LF
+    /// line endings, obsolete line folding, whitespace before a header colon, extra
+    /// spaces in the request line and Content-Length next to Transfer-Encoding.
+    #[default]
+    Strict,
+    /// Accept all of the above, unfolding folded lines and letting Transfer-Encoding
+    /// win over Content-Length. Meant for tests and hand typed requests. Content-Length
+    /// values that disagree are rejected either way.
+    Lenient,
+}
+
+/// Upper bounds on the parts of a request, so that a client can't make us buffer
+/// without end. Sizes are in bytes and include line endings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParserLimits {
+    /// Longest request line, which is mostly the request target
+    pub max_request_line: usize,
+    /// Most header fields in one request
+    pub max_headers: usize,
+    /// Largest header section, request line excluded. Also caps chunked trailers.
+    pub max_header_section: usize,
+    /// Largest body, whether framed by Content-Length or chunked
+    pub max_body_size: usize,
+}
+
+impl Default for ParserLimits {
+    fn default() -> Self {
+        Self {
+            max_request_line: 8 * 1024,
+            max_headers: 100,
+            max_header_section: 64 * 1024,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+        }
+    }
+}
+
+/// Where the parser currently is within a request
+#[derive(Debug)]
+enum State {
+    RequestLine,
+    Headers,
+    /// Waiting for the body to end as `Framing` says
+    Body(Framing),
+}
+
+/// Received bytes that haven't been consumed yet, split into lines on demand
+#[derive(Debug, Default)]
+struct LineBuffer {
+    buf: Vec<u8>,
+    /// How much of `buf` has already been searched for a line ending
+    scanned: usize,
+}
+
+impl LineBuffer {
+    /// Take the next line out of the buffer without its line ending, along with
+    /// the number of bytes it took up, or `None` if no complete line has arrived
+    /// yet. Fails with `too_long` once the line, line ending included, can't fit in
+    /// `max` bytes.
+    fn next_line(
+        &mut self,
+        max: usize,
+        too_long: ParseError,
+        mode: ParseMode,
+    ) -> Result<Option<(String, usize)>, ParseError> {
+        let end = match self.buf[self.scanned..].iter().position(|&b| b == b'\n') {
+            Some(pos) => self.scanned + pos,
+            None if self.buf.len() >= max => return Err(too_long),
+            None => {
+                self.scanned = self.buf.len();
+                return Ok(None);
+            }
+        };
+        if end >= max {
+            return Err(too_long);
+        }
+        let mut line: Vec<u8> = self.buf.drain(..=end).collect();
+        self.scanned = 0;
+        line.pop();
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        } else if mode == ParseMode::Strict {
+            return Err(ParseError::BareLf);
+        }
+        String::from_utf8(line)
+            .map(|line| Some((line, end + 1)))
+            .map_err(|_| ParseError::InvalidUtf8)
+    }
+
+    /// Everything left in the buffer
+    fn take(&mut self) -> Vec<u8> {
+        self.scanned = 0;
+        std::mem::take(&mut self.buf)
+    }
+}
+
+/// How the end of a message body is found
+#[derive(Debug)]
+enum Framing {
+    /// The body is this many bytes long
+    Length(usize),
+    /// Decoding a `Transfer-Encoding: chunked` body
+    Chunked(ChunkedDecoder),
+    /// The body runs until the connection is closed, which only responses do
+    UntilClose,
+}
+
+/// Resumable request parser. Feed it chunks with `feed` until it reports
+/// `ParseStatus::Complete`, after which it is reset and ready for the next request.
+#[derive(Debug)]
+pub struct RequestParser {
+    state: State,
+    input: LineBuffer,
+    /// The request being assembled, present once the request line is in
+    request: Option<HttpRequest>,
+    /// Size of the header section received so far
+    header_section: usize,
+    mode: ParseMode,
+    limits: ParserLimits,
+}
+
+impl Default for RequestParser {
+    fn default() -> Self {
+        Self {
+            state: State::RequestLine,
+            input: LineBuffer::default(),
+            request: None,
+            header_section: 0,
+            mode: ParseMode::default(),
+            limits: ParserLimits::default(),
+        }
+    }
+}
+
+impl RequestParser {
+    /// A strict parser
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Switch the parser to `mode`
+    pub fn mode(mut self, mode: ParseMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Replace the default limits
+    pub fn limits(mut self, limits: ParserLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Whether the request line and headers are in and the body is being read
+    pub fn reading_body(&self) -> bool {
+        matches!(self.state, State::Body(_))
+    }
+
+    /// Append `chunk` to what has been received so far and advance as far as the
+    /// buffered bytes allow. An empty chunk is fine and simply re-examines the buffer.
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<ParseStatus, ParseError> {
+        self.input.buf.extend_from_slice(chunk);
+        loop {
+            match self.state {
+                State::RequestLine => {
+                    let max = self.limits.max_request_line;
+                    let too_long = ParseError::RequestLineTooLong;
+                    let line = match self.input.next_line(max, too_long, self.mode)? {
+                        Some((line, _)) => line,
+                        None => return Ok(ParseStatus::Incomplete),
+                    };
+                    // Clients may send stray CRLFs between pipelined requests
+                    if line.is_empty() {
+                        continue;
+                    }
+                    self.request = Some(parse_request_line(&line, self.mode)?);
+                    self.state = State::Headers;
+                }
+                State::Headers => {
+                    let max = self.limits.max_header_section - self.header_section;
+                    let too_long = ParseError::HeaderSectionTooLarge;
+                    let line = match self.input.next_line(max, too_long, self.mode)? {
+                        Some((line, size)) => {
+                            self.header_section += size;
+                            line
+                        }
+                        None => return Ok(ParseStatus::Incomplete),
+                    };
+                    let request = self.request.as_mut().expect("request line parsed");
+                    if line.is_empty() {
+                        let framing = body_framing(&request.headers, self.mode, &self.limits)?
+                            .unwrap_or(Framing::Length(0));
+                        self.state = State::Body(framing);
+                        continue;
+                    }
+                    add_header(&mut request.headers, &line, self.mode, &self.limits)?;
+                }
+                State::Body(ref mut framing) => {
+                    let request = self.request.as_mut().expect("request line parsed");
+                    if !read_body(framing, &mut self.input, &mut request.body)? {
+                        return Ok(ParseStatus::Incomplete);
+                    }
+                    if let Framing::Chunked(decoder) = framing {
+                        request.trailers = decoder.take_trailers();
+                    }
+                    return Ok(self.complete());
+                }
+            }
+        }
+    }
+
+    /// Hand out the assembled request and reset the parser for the next one
+    fn complete(&mut self) -> ParseStatus {
+        let request = self.request.take().expect("request line parsed");
+        let leftover = self.input.take();
+        self.state = State::RequestLine;
+        self.header_section = 0;
+        ParseStatus::Complete(request, leftover)
+    }
+}
+
+/// Add a header line to `headers`. A line starting with whitespace continues the
+/// previous field, which only a lenient parser accepts.
+fn add_header(
+    headers: &mut HeaderMap,
+    line: &str,
+    mode: ParseMode,
+    limits: &ParserLimits,
+) -> Result<(), ParseError> {
+    if line.starts_with([' ', '\t']) {
+        if mode == ParseMode::Strict {
+            return Err(ParseError::ObsoleteLineFolding);
+        }
+        if !headers.extend_last(line) {
+            return Err(ParseError::InvalidHeader);
+        }
+        return Ok(());
+    }
+    if headers.len() == limits.max_headers {
+        return Err(ParseError::TooManyHeaders);
+    }
+    let (key, value) = parse_header_line(line, mode)?;
+    headers.append(key, value);
+    Ok(())
+}
+
+/// Move body bytes from `input` into `body` and report whether the body is
+/// complete. A body that runs until the connection closes never is.
+fn read_body(
+    framing: &mut Framing,
+    input: &mut LineBuffer,
+    body: &mut Vec<u8>,
+) -> Result<bool, ParseError> {
+    match framing {
+        Framing::Length(len) => {
+            if input.buf.len() < *len {
+                return Ok(false);
+            }
+            body.extend(input.buf.drain(..*len));
+            Ok(true)
+        }
+        Framing::Chunked(decoder) => {
+            let consumed = decoder.decode(&input.buf, body)?;
+            input.buf.drain(..consumed);
+            Ok(decoder.is_done())
+        }
+        Framing::UntilClose => Ok(false),
+    }
+}
+
+/// Split a request line into its three parts. A strict parser wants them separated
+/// by exactly one space.
+fn parse_request_line(line: &str, mode: ParseMode) -> Result<HttpRequest, ParseError> {
+    let parts: Vec<&str> = match mode {
+        ParseMode::Strict => line.split(' ').collect(),
+        ParseMode::Lenient => line.split_whitespace().collect(),
+    };
+    let (method, resource, version) = match parts[..] {
+        [method, resource, version] => (method, resource, version),
+        _ => return Err(ParseError::MalformedRequestLine),
+    };
+    let method: Method = method.parse()?;
+    let resource: Uri = resource.parse()?;
+    // authority-form is reserved for CONNECT and asterisk-form for OPTIONS
+    let form_allowed = match resource.form() {
+        TargetForm::Origin | TargetForm::Absolute => method != Method::Connect,
+        TargetForm::Authority => method == Method::Connect,
+        TargetForm::Asterisk => method == Method::Options,
+    };
+    if !form_allowed {
+        return Err(ParseError::InvalidTarget);
+    }
+    let version: Version = version.parse()?;
+    if version == Version::V2_0 {
+        // HTTP/2 has a binary framing of its own and never uses this request line
+        return Err(ParseError::UnsupportedVersion);
+    }
+    Ok(HttpRequest {
+        method,
+        version,
+        resource,
+        headers: HeaderMap::new(),
+        body: Vec::new(),
+        trailers: HeaderMap::new(),
+    })
+}
+
+/// Split a "Key:Value" line at the first colon. `HeaderMap` trims the value.
+pub(crate) fn parse_header_line(
+    line: &str,
+    mode: ParseMode,
+) -> Result<(String, String), ParseError> {
+    let split_index = line.find(':').ok_or(ParseError::InvalidHeader)?;
+    let mut key = &line[..split_index];
+    let value = &line[split_index + 1..];
+    if key.ends_with([' ', '\t']) {
+        match mode {
+            ParseMode::Strict => return Err(ParseError::WhitespaceBeforeColon),
+            ParseMode::Lenient => key = key.trim_end_matches([' ', '\t']),
+        }
+    }
+    if !is_token(key) {
+        return Err(ParseError::InvalidHeader);
+    }
+    // NUL, CR and the like have no business in a value
+    if mode == ParseMode::Strict
+        && value.bytes().any(|b| b.is_ascii_control() && b != b'\t')
+    {
+        return Err(ParseError::InvalidHeader);
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Whether `s` is a non-empty token as defined in RFC 9110, section 5.6.2. Methods
+/// and header names are tokens.
+pub(crate) fn is_token(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(is_tchar)
+}
+
+pub(crate) fn is_tchar(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+/// Work out how the body is framed from the headers (RFC 9112, section 6.3), or
+/// `None` if they give neither a length nor a transfer coding. A chunked body is the
+/// only transfer coding we decode.
+fn body_framing(
+    headers: &HeaderMap,
+    mode: ParseMode,
+    limits: &ParserLimits,
+) -> Result<Option<Framing>, ParseError> {
+    let codings: Vec<&str> = headers
+        .get_all("Transfer-Encoding")
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|coding| !coding.is_empty())
+        .collect();
+    if codings.is_empty() {
+        let length = match content_length(headers)? {
+            Some(length) => length,
+            None => return Ok(None),
+        };
+        if length > limits.max_body_size {
+            return Err(ParseError::BodyTooLarge);
+        }
+        return Ok(Some(Framing::Length(length)));
+    }
+    // Which of the two a proxy goes by is anybody's guess, the classic way to smuggle
+    // a request past it
+    if mode == ParseMode::Strict && headers.contains("Content-Length") {
+        return Err(ParseError::ContentLengthWithTransferEncoding);
+    }
+    match codings[..] {
+        [coding] if coding.eq_ignore_ascii_case("chunked") => {
+            let decoder = ChunkedDecoder::with_limits(
+                limits.max_body_size,
+                limits.max_header_section,
+            );
+            Ok(Some(Framing::Chunked(decoder.mode(mode))))
+        }
+        _ => Err(ParseError::UnsupportedTransferEncoding),
+    }
+}
+
+/// Number of body bytes announced by the Content-Length header, if there is one.
+/// Repeated values, whether in several fields or a list, must all be the same.
+fn content_length(headers: &HeaderMap) -> Result<Option<usize>, ParseError> {
+    let mut length = None;
+    for value in headers.get_all("Content-Length").flat_map(|v| v.split(',')) {
+        let value = value.trim();
+        if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(ParseError::InvalidContentLength);
+        }
+        let value: usize = value
+            .parse()
+            .map_err(|_| ParseError::InvalidContentLength)?;
+        match length {
+            Some(length) if length != value => {
+                return Err(ParseError::ConflictingContentLength)
+            }
+            _ => length = Some(value),
+        }
+    }
+    Ok(length)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REQUEST: &[u8] = b"POST /api/orders HTTP/1.1\r\nHost: localhost:3000\r\n\
+        Content-Length: 11\r\n\r\nlorem ipsum";
+
+    fn expect_complete(status: ParseStatus) -> (HttpRequest, Vec<u8>) {
+        match status {
+            ParseStatus::Complete(request, leftover) => (request, leftover),
+            ParseStatus::Incomplete => panic!("request should be complete"),
+        }
+    }
+
+    #[test]
+    fn parses_request_fed_one_byte_at_a_time() {
+        let mut parser = RequestParser::new();
+        let (last, init) = REQUEST.split_last().unwrap();
+        for byte in init {
+            match parser.feed(&[*byte]) {
+                Ok(ParseStatus::Incomplete) => (),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        assert!(parser.reading_body());
+        let (request, leftover) = expect_complete(parser.feed(&[*last]).unwrap());
+        assert!(!parser.reading_body());
+        assert_eq!(request.method, Method::Post);
+        assert_eq!(request.resource.path(), "/api/orders");
+        assert_eq!(request.version, Version::V1_1);
+        assert_eq!(request.body, b"lorem ipsum");
+        assert!(leftover.is_empty());
+    }
+
+    #[test]
+    fn hands_back_bytes_of_the_next_request() {
+        let mut stream = REQUEST.to_vec();
+        stream.extend_from_slice(b"GET /index.html HTTP/1.1\r\nHo");
+
+        let mut parser = RequestParser::new();
+        let (first, leftover) = expect_complete(parser.feed(&stream).unwrap());
+        assert_eq!(first.get_url(), "/api/orders");
+        assert_eq!(leftover, b"GET /index.html HTTP/1.1\r\nHo".to_vec());
+
+        let mut parser = RequestParser::new();
+        assert!(matches!(
+            parser.feed(&leftover),
+            Ok(ParseStatus::Incomplete)
+        ));
+        let (second, leftover) = expect_complete(parser.feed(b"st: a\r\n\r\n").unwrap());
+        assert_eq!(second.get_url(), "/index.html");
+        assert!(second.body.is_empty());
+        assert!(leftover.is_empty());
+    }
+
+    #[test]
+    fn parser_is_reusable_after_a_request() {
+        let mut parser = RequestParser::new();
+        expect_complete(parser.feed(REQUEST).unwrap());
+        let (request, _) = expect_complete(parser.feed(REQUEST).unwrap());
+        assert_eq!(request.body, b"lorem ipsum");
+    }
+
+    #[test]
+    fn decodes_chunked_bodies() {
+        let request = b"POST /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+            4\r\nWiki\r\n6;ext\r\npedia \r\n0\r\nX-Checksum: 1f\r\n\r\nGET";
+        let mut parser = RequestParser::new();
+        for byte in &request[..20] {
+            assert!(matches!(parser.feed(&[*byte]), Ok(ParseStatus::Incomplete)));
+        }
+        let (request, leftover) = expect_complete(parser.feed(&request[20..]).unwrap());
+        assert_eq!(request.body, b"Wikipedia ");
+        assert_eq!(request.trailers.get("X-Checksum"), Some("1f"));
+        assert_eq!(leftover, b"GET");
+
+        let mut parser = RequestParser::new();
+        assert_eq!(
+            parser
+                .feed(b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n")
+                .unwrap_err(),
+            ParseError::UnsupportedTransferEncoding
+        );
+    }
+
+    #[test]
+    fn strict_mode_rejects_ambiguous_requests() {
+        let cases: [(&[u8], ParseError); 8] = [
+            (
+                b"POST / HTTP/1.1\r\nContent-Length: 3\r\nContent-Length: 4\r\n\r\n",
+                ParseError::ConflictingContentLength,
+            ),
+            (
+                b"POST / HTTP/1.1\r\nContent-Length: 3, 4\r\n\r\n",
+                ParseError::ConflictingContentLength,
+            ),
+            (
+                b"POST / HTTP/1.1\r\nContent-Length: 3\r\n\
+                  Transfer-Encoding: chunked\r\n\r\n",
+                ParseError::ContentLengthWithTransferEncoding,
+            ),
+            (
+                b"GET / HTTP/1.1\r\nX-Folded: a\r\n b\r\n\r\n",
+                ParseError::ObsoleteLineFolding,
+            ),
+            (
+                b"GET / HTTP/1.1\r\nHost : localhost\r\n\r\n",
+                ParseError::WhitespaceBeforeColon,
+            ),
+            (b"GET / HTTP/1.1\nHost: localhost\n\n", ParseError::BareLf),
+            (b"GET  / HTTP/1.1\r\n\r\n", ParseError::MalformedRequestLine),
+            (
+                b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\n\n",
+                ParseError::BareLf,
+            ),
+        ];
+        for (request, error) in cases.iter() {
+            let mut parser = RequestParser::new();
+            assert_eq!(parser.feed(request).unwrap_err(), *error);
+        }
+
+        // The same value twice leaves no doubt
+        let mut parser = RequestParser::new();
+        let request =
+            b"POST / HTTP/1.1\r\nContent-Length: 2\r\nContent-Length: 2\r\n\r\nok";
+        assert!(matches!(
+            parser.feed(request),
+            Ok(ParseStatus::Complete(..))
+        ));
+    }
+
+    #[test]
+    fn lenient_mode_accepts_sloppy_requests() {
+        let request = b"POST  /  HTTP/1.1\nHost : localhost\nX-Folded: a\n\tb\n\
+            Content-Length: 100\nTransfer-Encoding: chunked\n\n2\nok\n0\n\n";
+        let mut parser = RequestParser::new().mode(ParseMode::Lenient);
+        let (request, _) = expect_complete(parser.feed(request).unwrap());
+        assert_eq!(request.headers.get("Host"), Some("localhost"));
+        assert_eq!(request.headers.get("X-Folded"), Some("a b"));
+        assert_eq!(request.body, b"ok");
+
+        let mut parser = RequestParser::new().mode(ParseMode::Lenient);
+        assert_eq!(
+            parser
+                .feed(b"POST / HTTP/1.1\nContent-Length: 3\nContent-Length: 4\n\n")
+                .unwrap_err(),
+            ParseError::ConflictingContentLength
+        );
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let limits = ParserLimits {
+            max_request_line: 20,
+            max_headers: 2,
+            max_header_section: 32,
+            max_body_size: 4,
+        };
+        let parse = |request: &[u8]| RequestParser::new().limits(limits).feed(request);
+
+        assert!(matches!(
+            parse(b"GET /01 HTTP/1.1\r\n\r\n"),
+            Ok(ParseStatus::Complete(..))
+        ));
+        // Caught before the line is even complete
+        assert_eq!(
+            parse(b"GET /012345678901234").unwrap_err(),
+            ParseError::RequestLineTooLong
+        );
+        assert_eq!(
+            parse(b"GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n").unwrap_err(),
+            ParseError::TooManyHeaders
+        );
+        assert_eq!(
+            parse(b"GET / HTTP/1.1\r\nA: 0123456789abcdef\r\nB: 0123456789abcdef\r\n")
+                .unwrap_err(),
+            ParseError::HeaderSectionTooLarge
+        );
+        assert_eq!(
+            parse(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\n").unwrap_err(),
+            ParseError::BodyTooLarge
+        );
+        assert_eq!(
+            parse(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\n")
+                .unwrap_err(),
+            ParseError::BodyTooLarge
+        );
+    }
+
+    #[test]
+    fn reports_errors() {
+        let mut parser = RequestParser::new();
+        assert_eq!(
+            parser.feed(b"GET /index.html\r\n").unwrap_err(),
+            ParseError::MalformedRequestLine
+        );
+
+        let mut parser = RequestParser::new();
+        assert_eq!(
+            parser
+                .feed(b"GET / HTTP/1.1\r\nno colon here\r\n")
+                .unwrap_err(),
+            ParseError::InvalidHeader
+        );
+
+        let mut parser = RequestParser::new();
+        assert_eq!(
+            parser.feed(b"G(E)T / HTTP/1.1\r\n").unwrap_err(),
+            ParseError::InvalidMethod
+        );
+
+        let mut parser = RequestParser::new();
+        assert_eq!(
+            parser.feed(b"GET / HTTP/2.0\r\n").unwrap_err(),
+            ParseError::UnsupportedVersion
+        );
+
+        let mut parser = RequestParser::new();
+        assert_eq!(
+            parser.feed(b"GET * HTTP/1.1\r\n").unwrap_err(),
+            ParseError::InvalidTarget
+        );
+
+        let mut parser = RequestParser::new();
+        assert_eq!(
+            parser
+                .feed(b"POST / HTTP/1.1\r\nContent-Length: -1\r\n\r\n")
+                .unwrap_err(),
+            ParseError::InvalidContentLength
+        );
+    }
+}
@@ -0,0 +1,339 @@
+//! Incremental parsing of HTTP responses, the client side counterpart of
+//! `RequestParser`
+//!
+//! Whether a response has a body depends on the request it answers: a response to
+//! HEAD never does, whatever its headers say. Tell the parser about the request with
+//! `set_request_method` before feeding it the response. A response that announces
+//! neither a length nor chunked framing runs until the server closes the connection,
+//! which has to be signalled with `eof`.
+use super::{
+    add_header, body_framing, read_body, Framing, LineBuffer, ParseError, ParseMode,
+    ParseStatus, ParserLimits,
+};
+use crate::headers::HeaderMap;
+use crate::http_request::{Method, Version};
+use crate::http_response::HttpResponse;
+use crate::status::StatusCode;
+
+/// Where the parser currently is within a response
+#[derive(Debug)]
+enum State {
+    StatusLine,
+    Headers,
+    /// Waiting for the body to end as `Framing` says
+    Body(Framing),
+}
+
+/// The parts of a response received so far
+#[derive(Debug)]
+struct Parts {
+    version: Version,
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Vec<u8>,
+}
+
+/// Resumable response parser. Feed it chunks with `feed` until it reports
+/// `ParseStatus::Complete`, after which it is reset and ready for the next response.
+/// Interim 1xx responses are handed out like any other.
+#[derive(Debug)]
+pub struct ResponseParser {
+    state: State,
+    input: LineBuffer,
+    /// The response being assembled, present once the status line is in
+    parts: Option<Parts>,
+    /// Size of the header section received so far
+    header_section: usize,
+    request_method: Method,
+    mode: ParseMode,
+    limits: ParserLimits,
+}
+
+impl Default for ResponseParser {
+    fn default() -> Self {
+        Self {
+            state: State::StatusLine,
+            input: LineBuffer::default(),
+            parts: None,
+            header_section: 0,
+            request_method: Method::Get,
+            mode: ParseMode::default(),
+            limits: ParserLimits::default(),
+        }
+    }
+}
+
+impl ResponseParser {
+    /// A strict parser expecting the response to a GET
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Switch the parser to `mode`
+    pub fn mode(mut self, mode: ParseMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Replace the default limits. `max_request_line` applies to the status line.
+    pub fn limits(mut self, limits: ParserLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// The method of the request the next response answers
+    pub fn set_request_method(&mut self, method: Method) {
+        self.request_method = method;
+    }
+
+    /// Append `chunk` to what has been received so far and advance as far as the
+    /// buffered bytes allow
+    pub fn feed(
+        &mut self,
+        chunk: &[u8],
+    ) -> Result<ParseStatus<HttpResponse>, ParseError> {
+        self.input.buf.extend_from_slice(chunk);
+        loop {
+            match self.state {
+                State::StatusLine => {
+                    let max = self.limits.max_request_line;
+                    let too_long = ParseError::MalformedStatusLine;
+                    let line = match self.input.next_line(max, too_long, self.mode)? {
+                        Some((line, _)) => line,
+                        None => return Ok(ParseStatus::Incomplete),
+                    };
+                    if line.is_empty() && self.mode == ParseMode::Lenient {
+                        continue;
+                    }
+                    let (version, status) = parse_status_line(&line, self.mode)?;
+                    self.parts = Some(Parts {
+                        version,
+                        status,
+                        headers: HeaderMap::new(),
+                        body: Vec::new(),
+                    });
+                    self.state = State::Headers;
+                }
+                State::Headers => {
+                    let max = self.limits.max_header_section - self.header_section;
+                    let too_long = ParseError::HeaderSectionTooLarge;
+                    let line = match self.input.next_line(max, too_long, self.mode)? {
+                        Some((line, size)) => {
+                            self.header_section += size;
+                            line
+                        }
+                        None => return Ok(ParseStatus::Incomplete),
+                    };
+                    let parts = self.parts.as_mut().expect("status line parsed");
+                    if line.is_empty() {
+                        self.state = State::Body(self.framing()?);
+                        continue;
+                    }
+                    add_header(&mut parts.headers, &line, self.mode, &self.limits)?;
+                }
+                State::Body(ref mut framing) => {
+                    let parts = self.parts.as_mut().expect("status line parsed");
+                    if !read_body(framing, &mut self.input, &mut parts.body)? {
+                        if let Framing::UntilClose = framing {
+                            if self.input.buf.len() > self.limits.max_body_size {
+                                return Err(ParseError::BodyTooLarge);
+                            }
+                        }
+                        return Ok(ParseStatus::Incomplete);
+                    }
+                    let trailers = match framing {
+                        Framing::Chunked(decoder) => decoder.take_trailers(),
+                        _ => HeaderMap::new(),
+                    };
+                    return Ok(self.complete(trailers));
+                }
+            }
+        }
+    }
+
+    /// The server closed the connection. This ends a body that runs until the
+    /// close, and anything else cut short is an error.
+    pub fn eof(&mut self) -> Result<HttpResponse, ParseError> {
+        match self.state {
+            State::Body(Framing::UntilClose) => {
+                let body = self.input.take();
+                self.parts.as_mut().expect("status line parsed").body = body;
+                match self.complete(HeaderMap::new()) {
+                    ParseStatus::Complete(response, _) => Ok(response),
+                    ParseStatus::Incomplete => unreachable!(),
+                }
+            }
+            _ => Err(ParseError::Incomplete),
+        }
+    }
+
+    /// How the body of the response whose headers were just read ends (RFC 9112,
+    /// section 6.3)
+    fn framing(&self) -> Result<Framing, ParseError> {
+        let parts = self.parts.as_ref().expect("status line parsed");
+        let status = parts.status;
+        let no_body = self.request_method == Method::Head
+            || status.is_informational()
+            || status == StatusCode::NO_CONTENT
+            || status == StatusCode::NOT_MODIFIED
+            // A successful CONNECT turns the connection into a tunnel
+            || (self.request_method == Method::Connect && status.is_success());
+        if no_body {
+            return Ok(Framing::Length(0));
+        }
+        let framing = body_framing(&parts.headers, self.mode, &self.limits)?;
+        Ok(framing.unwrap_or(Framing::UntilClose))
+    }
+
+    /// Hand out the assembled response and reset the parser for the next one
+    fn complete(&mut self, trailers: HeaderMap) -> ParseStatus<HttpResponse> {
+        let parts = self.parts.take().expect("status line parsed");
+        let response = HttpResponse::from_parts(
+            parts.version,
+            parts.status,
+            parts.headers,
+            parts.body,
+            trailers,
+        );
+        let leftover = self.input.take();
+        self.state = State::StatusLine;
+        self.header_section = 0;
+        ParseStatus::Complete(response, leftover)
+    }
+}
+
+/// Split a status line into version and status code. The reason phrase is only
+/// checked, `HttpResponse` uses the canonical one. A lenient parser doesn't mind a
+/// missing reason phrase.
+fn parse_status_line(
+    line: &str,
+    mode: ParseMode,
+) -> Result<(Version, StatusCode), ParseError> {
+    let mut parts = line.splitn(3, ' ');
+    let version = parts.next().unwrap_or_default();
+    let status = parts.next().ok_or(ParseError::MalformedStatusLine)?;
+    match parts.next() {
+        None if mode == ParseMode::Strict => return Err(ParseError::MalformedStatusLine),
+        Some(reason)
+            if mode == ParseMode::Strict
+                && reason.bytes().any(|b| b.is_ascii_control() && b != b'\t') =>
+        {
+            return Err(ParseError::MalformedStatusLine)
+        }
+        _ => (),
+    }
+    let version: Version = version.parse().map_err(|e| match e {
+        ParseError::MalformedRequestLine => ParseError::MalformedStatusLine,
+        e => e,
+    })?;
+    if version == Version::V2_0 {
+        return Err(ParseError::UnsupportedVersion);
+    }
+    Ok((version, status.parse()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expect_complete(
+        status: Result<ParseStatus<HttpResponse>, ParseError>,
+    ) -> (HttpResponse, Vec<u8>) {
+        match status.unwrap() {
+            ParseStatus::Complete(response, leftover) => (response, leftover),
+            ParseStatus::Incomplete => panic!("response should be complete"),
+        }
+    }
+
+    #[test]
+    fn parses_content_length_body_byte_at_a_time() {
+        let raw = b"HTTP/1.1 404 Not Found\r\nContent-Type: text/plain\r\n\
+            Content-Length: 5\r\n\r\nnope!HTTP/1.1";
+        let mut parser = ResponseParser::new();
+        let mut result = None;
+        for (i, byte) in raw.iter().enumerate() {
+            match parser.feed(&[*byte]).unwrap() {
+                ParseStatus::Incomplete => (),
+                ParseStatus::Complete(response, leftover) => {
+                    assert_eq!(i, raw.len() - "HTTP/1.1".len() - 1);
+                    result = Some((response, leftover));
+                    break;
+                }
+            }
+        }
+        let (response, _) = result.expect("response should be complete");
+        assert_eq!(response.version(), Version::V1_1);
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers().get("content-type"), Some("text/plain"));
+        assert_eq!(response.body().as_bytes(), Some(&b"nope!"[..]));
+    }
+
+    #[test]
+    fn parses_chunked_body_with_trailers() {
+        let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+            5\r\nhello\r\n0\r\nX-Checksum: 42\r\n\r\nHTTP/1.1 204 No Content\r\n\r\n";
+        let mut parser = ResponseParser::new();
+        let (response, leftover) = expect_complete(parser.feed(raw));
+        assert_eq!(response.body().as_bytes(), Some(&b"hello"[..]));
+        assert_eq!(response.trailers().get("x-checksum"), Some("42"));
+
+        let (response, leftover) = expect_complete(parser.feed(&leftover));
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(leftover.is_empty());
+    }
+
+    #[test]
+    fn reads_close_delimited_body_until_eof() {
+        let mut parser = ResponseParser::new();
+        let status = parser.feed(b"HTTP/1.0 200 OK\r\n\r\nall of ").unwrap();
+        assert!(matches!(status, ParseStatus::Incomplete));
+        assert!(matches!(
+            parser.feed(b"this").unwrap(),
+            ParseStatus::Incomplete
+        ));
+        let response = parser.eof().unwrap();
+        assert_eq!(response.version(), Version::V1_0);
+        assert_eq!(response.body().as_bytes(), Some(&b"all of this"[..]));
+
+        let mut parser = ResponseParser::new();
+        parser
+            .feed(b"HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\nabc")
+            .unwrap();
+        assert_eq!(parser.eof().unwrap_err(), ParseError::Incomplete);
+    }
+
+    #[test]
+    fn knows_which_responses_have_no_body() {
+        let mut parser = ResponseParser::new();
+        parser.set_request_method(Method::Head);
+        let (response, leftover) =
+            expect_complete(parser.feed(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n"));
+        assert!(response.body().is_empty());
+        assert!(leftover.is_empty());
+
+        let mut parser = ResponseParser::new();
+        let raw = b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 304 Not Modified\r\n\r\n";
+        let (response, leftover) = expect_complete(parser.feed(raw));
+        assert_eq!(response.status(), StatusCode::CONTINUE);
+        let (response, _) = expect_complete(parser.feed(&leftover));
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    }
+
+    #[test]
+    fn reports_status_line_errors() {
+        let cases: [(&[u8], ParseError); 5] = [
+            (b"HTTP/1.1 200\r\n", ParseError::MalformedStatusLine),
+            (b"HTTP/1.1 2000 OK\r\n", ParseError::InvalidStatusCode),
+            (b"HTTP/1.1 099 Odd\r\n", ParseError::InvalidStatusCode),
+            (b"HTTP/9.9 200 OK\r\n", ParseError::UnsupportedVersion),
+            (b"HTTX/1.1 200 OK\r\n", ParseError::MalformedStatusLine),
+        ];
+        for (raw, error) in cases.iter() {
+            assert_eq!(ResponseParser::new().feed(raw).unwrap_err(), *error);
+        }
+        let mut lenient = ResponseParser::new().mode(ParseMode::Lenient);
+        let (response, _) =
+            expect_complete(lenient.feed(b"HTTP/1.1 200\nContent-Length: 0\n\n"));
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
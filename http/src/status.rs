@@ -0,0 +1,228 @@
+//! HTTP status codes
+//!
+//! `StatusCode` accepts any three digit code from 100 to 599, since a response may
+//! carry a code we have never heard of (RFC 9110, section 15). Codes in the IANA
+//! HTTP Status Code Registry come with a named constant and their canonical reason
+//! phrase.
+use crate::parser::ParseError;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+/// A status code between 100 and 599
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StatusCode(u16);
+
+macro_rules! status_codes {
+    ($( $(#[$doc:meta])* ($code:expr, $name:ident, $reason:expr); )+) => {
+        impl StatusCode {
+            $(
+                $(#[$doc])*
+                pub const $name: StatusCode = StatusCode($code);
+            )+
+
+            /// The reason phrase the registry gives this code, if it is registered
+            pub fn canonical_reason(&self) -> Option<&'static str> {
+                match self.0 {
+                    $( $code => Some($reason), )+
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+// https://www.iana.org/assignments/http-status-codes, leaving out the codes
+// registered as "(Unused)"
+status_codes! {
+    (100, CONTINUE, "Continue");
+    (101, SWITCHING_PROTOCOLS, "Switching Protocols");
+    (102, PROCESSING, "Processing");
+    (103, EARLY_HINTS, "Early Hints");
+
+    (200, OK, "OK");
+    (201, CREATED, "Created");
+    (202, ACCEPTED, "Accepted");
+    (203, NON_AUTHORITATIVE_INFORMATION, "Non-Authoritative Information");
+    (204, NO_CONTENT, "No Content");
+    (205, RESET_CONTENT, "Reset Content");
+    (206, PARTIAL_CONTENT, "Partial Content");
+    (207, MULTI_STATUS, "Multi-Status");
+    (208, ALREADY_REPORTED, "Already Reported");
+    (226, IM_USED, "IM Used");
+
+    (300, MULTIPLE_CHOICES, "Multiple Choices");
+    (301, MOVED_PERMANENTLY, "Moved Permanently");
+    (302, FOUND, "Found");
+    (303, SEE_OTHER, "See Other");
+    (304, NOT_MODIFIED, "Not Modified");
+    (305, USE_PROXY, "Use Proxy");
+    (307, TEMPORARY_REDIRECT, "Temporary Redirect");
+    (308, PERMANENT_REDIRECT, "Permanent Redirect");
+
+    (400, BAD_REQUEST, "Bad Request");
+    (401, UNAUTHORIZED, "Unauthorized");
+    (402, PAYMENT_REQUIRED, "Payment Required");
+    (403, FORBIDDEN, "Forbidden");
+    (404, NOT_FOUND, "Not Found");
+    (405, METHOD_NOT_ALLOWED, "Method Not Allowed");
+    (406, NOT_ACCEPTABLE, "Not Acceptable");
+    (407, PROXY_AUTHENTICATION_REQUIRED, "Proxy Authentication Required");
+    (408, REQUEST_TIMEOUT, "Request Timeout");
+    (409, CONFLICT, "Conflict");
+    (410, GONE, "Gone");
+    (411, LENGTH_REQUIRED, "Length Required");
+    (412, PRECONDITION_FAILED, "Precondition Failed");
+    (413, CONTENT_TOO_LARGE, "Content Too Large");
+    (414, URI_TOO_LONG, "URI Too Long");
+    (415, UNSUPPORTED_MEDIA_TYPE, "Unsupported Media Type");
+    (416, RANGE_NOT_SATISFIABLE, "Range Not Satisfiable");
+    (417, EXPECTATION_FAILED, "Expectation Failed");
+    (421, MISDIRECTED_REQUEST, "Misdirected Request");
+    (422, UNPROCESSABLE_CONTENT, "Unprocessable Content");
+    (423, LOCKED, "Locked");
+    (424, FAILED_DEPENDENCY, "Failed Dependency");
+    (425, TOO_EARLY, "Too Early");
+    (426, UPGRADE_REQUIRED, "Upgrade Required");
+    (428, PRECONDITION_REQUIRED, "Precondition Required");
+    (429, TOO_MANY_REQUESTS, "Too Many Requests");
+    (431, REQUEST_HEADER_FIELDS_TOO_LARGE, "Request Header Fields Too Large");
+    (451, UNAVAILABLE_FOR_LEGAL_REASONS, "Unavailable For Legal Reasons");
+
+    (500, INTERNAL_SERVER_ERROR, "Internal Server Error");
+    (501, NOT_IMPLEMENTED, "Not Implemented");
+    (502, BAD_GATEWAY, "Bad Gateway");
+    (503, SERVICE_UNAVAILABLE, "Service Unavailable");
+    (504, GATEWAY_TIMEOUT, "Gateway Timeout");
+    (505, HTTP_VERSION_NOT_SUPPORTED, "HTTP Version Not Supported");
+    (506, VARIANT_ALSO_NEGOTIATES, "Variant Also Negotiates");
+    (507, INSUFFICIENT_STORAGE, "Insufficient Storage");
+    (508, LOOP_DETECTED, "Loop Detected");
+    /// Obsoleted by RFC 9110 but still registered
+    (510, NOT_EXTENDED, "Not Extended");
+    (511, NETWORK_AUTHENTICATION_REQUIRED, "Network Authentication Required");
+}
+
+impl StatusCode {
+    /// The code for `code`, if it lies between 100 and 599
+    pub fn from_u16(code: u16) -> Result<StatusCode, ParseError> {
+        if (100..600).contains(&code) {
+            Ok(StatusCode(code))
+        } else {
+            Err(ParseError::InvalidStatusCode)
+        }
+    }
+
+    pub fn as_u16(&self) -> u16 {
+        self.0
+    }
+
+    /// The reason phrase to send with this code. Unregistered codes fall back to
+    /// a generic phrase for their class.
+    pub fn reason(&self) -> &'static str {
+        self.canonical_reason().unwrap_or(match self.0 / 100 {
+            1 => "Informational",
+            2 => "Success",
+            3 => "Redirection",
+            4 => "Client Error",
+            _ => "Server Error",
+        })
+    }
+
+    /// 1xx: the request was received and is being processed
+    pub fn is_informational(&self) -> bool {
+        self.0 < 200
+    }
+
+    /// 2xx: the request was accepted
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.0)
+    }
+
+    /// 3xx: the client has to go somewhere else
+    pub fn is_redirection(&self) -> bool {
+        (300..400).contains(&self.0)
+    }
+
+    /// 4xx: the request was at fault
+    pub fn is_client_error(&self) -> bool {
+        (400..500).contains(&self.0)
+    }
+
+    /// 5xx: the server was at fault
+    pub fn is_server_error(&self) -> bool {
+        self.0 >= 500
+    }
+}
+
+impl Default for StatusCode {
+    fn default() -> Self {
+        StatusCode::OK
+    }
+}
+
+impl TryFrom<u16> for StatusCode {
+    type Error = ParseError;
+
+    fn try_from(code: u16) -> Result<Self, Self::Error> {
+        StatusCode::from_u16(code)
+    }
+}
+
+/// Parse the three digits of a status line
+impl FromStr for StatusCode {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != 3 || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(ParseError::InvalidStatusCode);
+        }
+        StatusCode::from_u16(s.parse().unwrap())
+    }
+}
+
+/// Just the three digits, as they appear on a status line
+impl fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registered_codes_have_reasons() {
+        assert_eq!(StatusCode::NOT_FOUND.as_u16(), 404);
+        assert_eq!(StatusCode::NOT_FOUND.reason(), "Not Found");
+        assert_eq!(StatusCode::IM_USED.canonical_reason(), Some("IM Used"));
+        assert_eq!(StatusCode::from_u16(418).unwrap().canonical_reason(), None);
+        assert_eq!(StatusCode::from_u16(418).unwrap().reason(), "Client Error");
+        assert_eq!(StatusCode::from_u16(599).unwrap().reason(), "Server Error");
+    }
+
+    #[test]
+    fn parses_and_displays_codes() {
+        assert_eq!("201".parse(), Ok(StatusCode::CREATED));
+        assert_eq!(StatusCode::CREATED.to_string(), "201");
+        let invalid = ["99", "600", "1000", "+20", "abc", ""];
+        for code in invalid.iter() {
+            assert_eq!(
+                code.parse::<StatusCode>(),
+                Err(ParseError::InvalidStatusCode)
+            );
+        }
+        assert_eq!(StatusCode::try_from(99), Err(ParseError::InvalidStatusCode));
+    }
+
+    #[test]
+    fn classifies_codes() {
+        assert!(StatusCode::CONTINUE.is_informational());
+        assert!(StatusCode::NO_CONTENT.is_success());
+        assert!(StatusCode::SEE_OTHER.is_redirection());
+        assert!(StatusCode::GONE.is_client_error());
+        assert!(StatusCode::BAD_GATEWAY.is_server_error());
+        assert!(!StatusCode::OK.is_client_error());
+    }
+}
@@ -0,0 +1,477 @@
+//! Parsed request targets and URIs
+//!
+//! A request line can name its target in four ways (RFC 9112, section 3.2):
+//!
+//! * origin-form: `/where?q=now`, used for almost every request
+//! * absolute-form: `http://www.example.org/pub/WWW/TheProject.html`, sent to proxies
+//! * authority-form: `www.example.com:80`, only used by CONNECT
+//! * asterisk-form: `*`, only used by a server-wide OPTIONS
+//!
+//! `Uri` parses all four. Dot segments are removed from the path and escapes of
+//! unreserved characters are decoded, so `/a/./b/../%63` and `/a/c` compare equal.
+//! Everything else stays percent-encoded until asked for through the decoding
+//! accessors.
+use crate::parser::ParseError;
+use std::fmt;
+use std::str::FromStr;
+
+/// The shape a request target was written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetForm {
+    Origin,
+    Absolute,
+    Authority,
+    Asterisk,
+}
+
+/// A parsed request target
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Uri {
+    form: TargetForm,
+    /// Lowercased scheme of an absolute URI
+    scheme: Option<String>,
+    /// `host[:port]` of an absolute URI or an authority-form target
+    authority: Option<String>,
+    /// Normalized path, still percent-encoded
+    path: String,
+    query: Option<String>,
+    fragment: Option<String>,
+}
+
+impl Uri {
+    pub fn form(&self) -> TargetForm {
+        self.form
+    }
+
+    pub fn scheme(&self) -> Option<&str> {
+        self.scheme.as_deref()
+    }
+
+    pub fn authority(&self) -> Option<&str> {
+        self.authority.as_deref()
+    }
+
+    /// Host part of the authority, without the port. IPv6 literals keep their
+    /// brackets.
+    pub fn host(&self) -> Option<&str> {
+        Some(split_port(self.host_port()?).0)
+    }
+
+    /// Port given in the authority, if any
+    pub fn port(&self) -> Option<u16> {
+        let port = split_port(self.host_port()?).1?;
+        port.parse().ok()
+    }
+
+    /// The authority without its `userinfo@`, whose password may contain colons
+    fn host_port(&self) -> Option<&str> {
+        let authority = self.authority.as_deref()?;
+        authority.rsplit('@').next()
+    }
+
+    /// The normalized, percent-encoded path. `*` for an asterisk-form target and
+    /// empty for an authority-form one.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The path with percent escapes decoded. Bytes that aren't valid UTF-8 are
+    /// replaced with U+FFFD.
+    pub fn decoded_path(&self) -> String {
+        String::from_utf8_lossy(&percent_decode(&self.path)).into_owned()
+    }
+
+    /// Decoded path segments, so `/api/shipping/orders` gives
+    /// `["api", "shipping", "orders"]` and `/` gives `[""]`. Since each segment is
+    /// decoded on its own, an escaped `%2F` ends up inside a segment rather than
+    /// splitting it.
+    pub fn path_segments(&self) -> Vec<String> {
+        match self.path.strip_prefix('/') {
+            Some(path) => path
+                .split('/')
+                .map(|segment| {
+                    String::from_utf8_lossy(&percent_decode(segment)).into_owned()
+                })
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// The raw query string without the leading `?`
+    pub fn query(&self) -> Option<&str> {
+        self.query.as_deref()
+    }
+
+    /// Decoded `key=value` pairs of an `application/x-www-form-urlencoded` query
+    /// string, in order. A `+` stands for a space and a pair without `=` has an
+    /// empty value.
+    pub fn query_pairs(&self) -> Vec<(String, String)> {
+        let query = match self.query.as_deref() {
+            Some(query) => query,
+            None => return Vec::new(),
+        };
+        query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = match pair.find('=') {
+                    Some(index) => (&pair[..index], &pair[index + 1..]),
+                    None => (pair, ""),
+                };
+                (decode_form_component(key), decode_form_component(value))
+            })
+            .collect()
+    }
+
+    pub fn fragment(&self) -> Option<&str> {
+        self.fragment.as_deref()
+    }
+
+    /// Path and query as they appear on an origin-form request line
+    pub fn path_and_query(&self) -> String {
+        match &self.query {
+            Some(query) => format!("{}?{}", self.path, query),
+            None => self.path.clone(),
+        }
+    }
+
+    /// The URI as it goes on a request line, which is all of it but the fragment
+    pub fn request_target(&self) -> String {
+        match (&self.scheme, &self.authority) {
+            (Some(scheme), Some(authority)) => {
+                format!("{}://{}{}", scheme, authority, self.path_and_query())
+            }
+            (None, Some(authority)) => authority.clone(),
+            _ => self.path_and_query(),
+        }
+    }
+}
+
+impl FromStr for Uri {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() || !s.bytes().all(is_uri_char) || !valid_escapes(s) {
+            return Err(ParseError::InvalidTarget);
+        }
+        if s == "*" {
+            return Ok(Uri {
+                form: TargetForm::Asterisk,
+                scheme: None,
+                authority: None,
+                path: "*".to_string(),
+                query: None,
+                fragment: None,
+            });
+        }
+        if s.starts_with('/') {
+            let (path, query, fragment) = split_path_query_fragment(s);
+            return Ok(Uri {
+                form: TargetForm::Origin,
+                scheme: None,
+                authority: None,
+                path: normalize_path(path),
+                query,
+                fragment,
+            });
+        }
+        if let Some(index) = s.find("://") {
+            let scheme = &s[..index];
+            if !is_scheme(scheme) {
+                return Err(ParseError::InvalidTarget);
+            }
+            let rest = &s[index + 3..];
+            let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+            let authority = &rest[..end];
+            if authority.is_empty() {
+                return Err(ParseError::InvalidTarget);
+            }
+            let (path, query, fragment) = split_path_query_fragment(&rest[end..]);
+            let path = if path.is_empty() { "/" } else { path };
+            return Ok(Uri {
+                form: TargetForm::Absolute,
+                scheme: Some(scheme.to_ascii_lowercase()),
+                authority: Some(authority.to_string()),
+                path: normalize_path(path),
+                query,
+                fragment,
+            });
+        }
+        // authority-form is nothing but host:port
+        match split_port(s) {
+            (host, Some(port))
+                if !host.is_empty()
+                    && !s.contains(['/', '?', '#', '@'])
+                    && port.parse::<u16>().is_ok() =>
+            {
+                Ok(Uri {
+                    form: TargetForm::Authority,
+                    scheme: None,
+                    authority: Some(s.to_string()),
+                    path: String::new(),
+                    query: None,
+                    fragment: None,
+                })
+            }
+            _ => Err(ParseError::InvalidTarget),
+        }
+    }
+}
+
+impl fmt::Display for Uri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(scheme) = &self.scheme {
+            write!(f, "{}://", scheme)?;
+        }
+        if let Some(authority) = &self.authority {
+            f.write_str(authority)?;
+        }
+        f.write_str(&self.path_and_query())?;
+        if let Some(fragment) = &self.fragment {
+            write!(f, "#{}", fragment)?;
+        }
+        Ok(())
+    }
+}
+
+/// Decode `%XX` escapes. Anything that isn't a valid escape is kept as is.
+pub fn percent_decode(s: &str) -> Vec<u8> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let (Some(hi), Some(lo)) =
+                (hex_value(bytes[i + 1]), hex_value(bytes[i + 2]))
+            {
+                decoded.push(hi << 4 | lo);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    decoded
+}
+
+fn decode_form_component(s: &str) -> String {
+    String::from_utf8_lossy(&percent_decode(&s.replace('+', " "))).into_owned()
+}
+
+/// Split `path?query#fragment` into its parts
+fn split_path_query_fragment(s: &str) -> (&str, Option<String>, Option<String>) {
+    let (rest, fragment) = match s.find('#') {
+        Some(index) => (&s[..index], Some(s[index + 1..].to_string())),
+        None => (s, None),
+    };
+    match rest.find('?') {
+        Some(index) => (
+            &rest[..index],
+            Some(rest[index + 1..].to_string()),
+            fragment,
+        ),
+        None => (rest, None, fragment),
+    }
+}
+
+/// Split `host:port`, minding the colons inside an IPv6 literal
+fn split_port(authority: &str) -> (&str, Option<&str>) {
+    let host_end = match authority.rfind(']') {
+        Some(index) => index + 1,
+        None => 0,
+    };
+    match authority[host_end..].rfind(':') {
+        Some(index) => {
+            let index = host_end + index;
+            (&authority[..index], Some(&authority[index + 1..]))
+        }
+        None => (authority, None),
+    }
+}
+
+/// Decode escaped unreserved characters, uppercase the remaining escapes and
+/// remove `.` and `..` segments (RFC 3986, sections 6.2.2 and 5.2.4)
+fn normalize_path(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut normalized = String::with_capacity(path.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            // escapes were validated while parsing
+            let byte =
+                hex_value(bytes[i + 1]).unwrap() << 4 | hex_value(bytes[i + 2]).unwrap();
+            if is_unreserved(byte) {
+                normalized.push(byte as char);
+            } else {
+                normalized.push_str(&format!("%{:02X}", byte));
+            }
+            i += 3;
+        } else {
+            normalized.push(bytes[i] as char);
+            i += 1;
+        }
+    }
+    remove_dot_segments(&normalized)
+}
+
+/// RFC 3986, section 5.2.4
+fn remove_dot_segments(path: &str) -> String {
+    let mut input = path;
+    let mut output: Vec<&str> = Vec::new();
+    while !input.is_empty() {
+        if let Some(rest) = input.strip_prefix("../") {
+            input = rest;
+        } else if let Some(rest) = input.strip_prefix("./") {
+            input = rest;
+        } else if input.starts_with("/./") {
+            input = &input[2..];
+        } else if input == "/." {
+            input = "/";
+        } else if input.starts_with("/../") {
+            input = &input[3..];
+            output.pop();
+        } else if input == "/.." {
+            input = "/";
+            output.pop();
+        } else if input == "." || input == ".." {
+            input = "";
+        } else {
+            let start = if input.starts_with('/') { 1 } else { 0 };
+            let end = input[start..].find('/').map_or(input.len(), |i| i + start);
+            output.push(&input[..end]);
+            input = &input[end..];
+        }
+    }
+    output.concat()
+}
+
+/// Characters allowed anywhere in a URI: unreserved, reserved and `%`
+fn is_uri_char(b: u8) -> bool {
+    is_unreserved(b) || b"%:/?#[]@!$&'()*+,;=".contains(&b)
+}
+
+fn is_unreserved(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"-._~".contains(&b)
+}
+
+fn is_scheme(s: &str) -> bool {
+    let mut bytes = s.bytes();
+    matches!(bytes.next(), Some(b) if b.is_ascii_alphabetic())
+        && bytes.all(|b| b.is_ascii_alphanumeric() || b"+-.".contains(&b))
+}
+
+/// Every `%` must be followed by two hex digits
+fn valid_escapes(s: &str) -> bool {
+    let bytes = s.as_bytes();
+    bytes.iter().enumerate().all(|(i, &b)| {
+        b != b'%'
+            || (bytes.get(i + 1).and_then(|&b| hex_value(b)).is_some()
+                && bytes.get(i + 2).and_then(|&b| hex_value(b)).is_some())
+    })
+}
+
+fn hex_value(b: u8) -> Option<u8> {
+    (b as char).to_digit(16).map(|d| d as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn origin_form() {
+        let uri: Uri = "/api/shipping/orders?id=42&name=J%C3%B6rg+S"
+            .parse()
+            .unwrap();
+        assert_eq!(uri.form(), TargetForm::Origin);
+        assert_eq!(uri.path(), "/api/shipping/orders");
+        assert_eq!(uri.path_segments(), ["api", "shipping", "orders"]);
+        assert_eq!(uri.query(), Some("id=42&name=J%C3%B6rg+S"));
+        assert_eq!(
+            uri.query_pairs(),
+            [
+                ("id".to_string(), "42".to_string()),
+                ("name".to_string(), "Jörg S".to_string())
+            ]
+        );
+        assert_eq!(uri.host(), None);
+        assert_eq!(
+            uri.to_string(),
+            "/api/shipping/orders?id=42&name=J%C3%B6rg+S"
+        );
+
+        let root: Uri = "/".parse().unwrap();
+        assert_eq!(root.path_segments(), [""]);
+    }
+
+    #[test]
+    fn absolute_form() {
+        let uri: Uri = "HTTP://example.org:8080/a/b/../c?x=1#top".parse().unwrap();
+        assert_eq!(uri.form(), TargetForm::Absolute);
+        assert_eq!(uri.scheme(), Some("http"));
+        assert_eq!(uri.authority(), Some("example.org:8080"));
+        assert_eq!(uri.host(), Some("example.org"));
+        assert_eq!(uri.port(), Some(8080));
+        assert_eq!(uri.path(), "/a/c");
+        assert_eq!(uri.fragment(), Some("top"));
+        assert_eq!(uri.to_string(), "http://example.org:8080/a/c?x=1#top");
+
+        let bare: Uri = "http://[::1]".parse().unwrap();
+        assert_eq!(bare.host(), Some("[::1]"));
+        assert_eq!(bare.port(), None);
+        assert_eq!(bare.path(), "/");
+
+        let user: Uri = "http://user:pa:ss@host/".parse().unwrap();
+        assert_eq!(user.host(), Some("host"));
+        assert_eq!(user.port(), None);
+        let user: Uri = "http://user:pa:ss@host:81/".parse().unwrap();
+        assert_eq!(user.port(), Some(81));
+    }
+
+    #[test]
+    fn authority_and_asterisk_form() {
+        let uri: Uri = "www.example.com:443".parse().unwrap();
+        assert_eq!(uri.form(), TargetForm::Authority);
+        assert_eq!(uri.host(), Some("www.example.com"));
+        assert_eq!(uri.port(), Some(443));
+        assert_eq!(uri.to_string(), "www.example.com:443");
+
+        let uri: Uri = "*".parse().unwrap();
+        assert_eq!(uri.form(), TargetForm::Asterisk);
+        assert_eq!(uri.to_string(), "*");
+    }
+
+    #[test]
+    fn normalizes_paths() {
+        let uri: Uri = "/a/./b/../%63/%2e%2E/d%2f".parse().unwrap();
+        assert_eq!(uri.path(), "/a/d%2F");
+        assert_eq!(uri.decoded_path(), "/a/d/");
+        assert_eq!(uri.path_segments(), ["a", "d/"]);
+
+        let uri: Uri = "/../../etc/passwd".parse().unwrap();
+        assert_eq!(uri.path(), "/etc/passwd");
+    }
+
+    #[test]
+    fn rejects_invalid_targets() {
+        for target in [
+            "",
+            "/a b",
+            "/a%2",
+            "/a%zz",
+            "/<script>",
+            "example.com",
+            "a:b:c",
+        ]
+        .iter()
+        {
+            assert_eq!(
+                target.parse::<Uri>(),
+                Err(ParseError::InvalidTarget),
+                "{}",
+                target
+            );
+        }
+    }
+}
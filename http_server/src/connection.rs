@@ -0,0 +1,305 @@
+//! Reading whole requests off a client connection
+//!
+//! A request rarely arrives in one piece: slow clients trickle it in, large ones
+//! span many reads, and a pipelining client may send the start of its next request
+//! along with this one. `Connection` keeps reading and feeding a `RequestParser`
+//! until a request is complete and holds on to whatever came after it.
+//!
+//! It also keeps track of how long each part of a request is taking, so that a
+//! client can't hold on to the connection by sending nothing, or next to nothing.
+
+use super::server::Timeouts;
+use http::http_request::HttpRequest;
+use http::parser::{ParseError, ParseStatus, ParserLimits, RequestParser};
+use std::fmt;
+use std::io::{self, Read};
+use std::time::Instant;
+
+/// How much to ask the socket for at a time
+const READ_SIZE: usize = 4096;
+
+/// Why no request could be read
+#[derive(Debug)]
+pub enum ReadError {
+    /// The client sent something that isn't a valid request
+    Parse(ParseError),
+    /// The socket failed
+    Io(io::Error),
+    /// The client took longer than the timeouts allow over this stage
+    Timeout(Stage),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::Parse(e) => write!(f, "{}", e),
+            ReadError::Io(e) => write!(f, "{}", e),
+            ReadError::Timeout(stage) => write!(f, "timed out reading {:?}", stage),
+        }
+    }
+}
+
+impl From<ParseError> for ReadError {
+    fn from(e: ParseError) -> Self {
+        ReadError::Parse(e)
+    }
+}
+
+impl From<io::Error> for ReadError {
+    fn from(e: io::Error) -> Self {
+        ReadError::Io(e)
+    }
+}
+
+/// The part of a request a connection is waiting for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Nothing of the next request has arrived
+    Idle,
+    /// The request line and headers
+    Headers,
+    /// The body
+    Body,
+}
+
+/// A client connection that requests are read from
+pub struct Connection<S> {
+    stream: S,
+    parser: RequestParser,
+    timeouts: Timeouts,
+    /// Bytes received past the end of the last request
+    pending: Vec<u8>,
+    stage: Stage,
+    /// When `stage` began. `None` between answering a request and reading the
+    /// next one: the time spent responding doesn't count against the client.
+    since: Option<Instant>,
+    last_read: Instant,
+    /// Bytes of the current body received so far
+    body_bytes: u64,
+}
+
+impl<S: Read> Connection<S> {
+    pub fn new(stream: S, limits: ParserLimits, timeouts: Timeouts) -> Self {
+        Connection {
+            stream,
+            parser: RequestParser::new().limits(limits),
+            timeouts,
+            pending: Vec::new(),
+            stage: Stage::Idle,
+            since: Some(Instant::now()),
+            last_read: Instant::now(),
+            body_bytes: 0,
+        }
+    }
+
+    /// The stream requests are read from, for writing responses back
+    pub fn stream(&self) -> &S {
+        &self.stream
+    }
+
+    /// Whether the connection is between requests, with nothing of the next one
+    /// received yet
+    pub fn is_idle(&self) -> bool {
+        self.stage == Stage::Idle && self.pending.is_empty()
+    }
+
+    /// The stage that has taken longer than the timeouts allow, if any. A body also
+    /// times out when, after arriving for `body_read`, it comes in slower than
+    /// `min_body_rate` on average.
+    pub fn timed_out(&self) -> Option<Stage> {
+        let timeouts = &self.timeouts;
+        let elapsed = self.since?.elapsed();
+        let expired = match self.stage {
+            Stage::Idle => elapsed >= timeouts.idle,
+            Stage::Headers => elapsed >= timeouts.header_read,
+            Stage::Body => {
+                let too_slow = timeouts.min_body_rate.is_some_and(|rate| {
+                    elapsed >= timeouts.body_read
+                        && (self.body_bytes as f64) < rate as f64 * elapsed.as_secs_f64()
+                });
+                too_slow || self.last_read.elapsed() >= timeouts.body_read
+            }
+        };
+        if expired {
+            Some(self.stage)
+        } else {
+            None
+        }
+    }
+
+    /// Read the next request, or `None` if the client closed the connection
+    /// before sending any of it. A read timeout, or `WouldBlock` on a non-blocking
+    /// stream, leaves what was received so far in place for the next call.
+    ///
+    /// The timeouts are checked before every read, so a client that keeps sending
+    /// a byte now and then can't stretch a request past them.
+    pub fn read_request(&mut self) -> Result<Option<HttpRequest>, ReadError> {
+        self.since.get_or_insert_with(Instant::now);
+        let pending = std::mem::take(&mut self.pending);
+        if !pending.is_empty() && self.stage == Stage::Idle {
+            self.enter(Stage::Headers);
+        }
+        let mut status = self.parser.feed(&pending)?;
+        let mut buf = [0_u8; READ_SIZE];
+        loop {
+            if let ParseStatus::Complete(req, leftover) = status {
+                self.pending = leftover;
+                self.stage = Stage::Idle;
+                self.since = None;
+                return Ok(Some(req));
+            }
+            if self.stage == Stage::Headers && self.parser.reading_body() {
+                self.enter(Stage::Body);
+            }
+            if self.stage != Stage::Idle {
+                if let Some(stage) = self.timed_out() {
+                    return Err(ReadError::Timeout(stage));
+                }
+            }
+            let bytes_read = match self.stream.read(&mut buf) {
+                Ok(0) if self.stage != Stage::Idle => {
+                    return Err(ParseError::Incomplete.into())
+                }
+                Ok(0) => return Ok(None),
+                Ok(bytes_read) => bytes_read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            self.last_read = Instant::now();
+            if self.stage == Stage::Idle {
+                self.enter(Stage::Headers);
+            }
+            if self.stage == Stage::Body {
+                self.body_bytes += bytes_read as u64;
+            }
+            status = self.parser.feed(&buf[..bytes_read])?;
+        }
+    }
+
+    fn enter(&mut self, stage: Stage) {
+        self.stage = stage;
+        self.since = Some(Instant::now());
+        self.body_bytes = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Hands out `chunks` one per read, each after `delay`, like a client that
+    /// never goes quiet for long but never gets anywhere either
+    struct Trickle {
+        chunks: VecDeque<Vec<u8>>,
+        delay: Duration,
+    }
+
+    impl Trickle {
+        fn new(first: &[u8], then: &[u8], delay: Duration) -> Self {
+            let mut chunks: VecDeque<Vec<u8>> = then.iter().map(|b| vec![*b]).collect();
+            chunks.push_front(first.to_vec());
+            Trickle { chunks, delay }
+        }
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            thread::sleep(self.delay);
+            let chunk = match self.chunks.pop_front() {
+                Some(chunk) => chunk,
+                None => return Ok(0),
+            };
+            buf[..chunk.len()].copy_from_slice(&chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    fn timeouts() -> Timeouts {
+        Timeouts {
+            header_read: Duration::from_millis(100),
+            body_read: Duration::from_millis(100),
+            min_body_rate: Some(1000),
+            ..Timeouts::default()
+        }
+    }
+
+    #[test]
+    fn reads_requests_arriving_in_time() {
+        let stream = Trickle::new(b"GET / HT", b"TP/1.1\r\n\r\n", Duration::ZERO);
+        let mut connection = Connection::new(stream, ParserLimits::default(), timeouts());
+        assert!(connection.read_request().unwrap().is_some());
+        assert!(connection.read_request().unwrap().is_none());
+    }
+
+    #[test]
+    fn reads_requests_spanning_several_reads() {
+        // A byte slice fills at most the READ_SIZE buffer on each read
+        let body = "x".repeat(3 * READ_SIZE);
+        let raw = format!(
+            "POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let mut connection =
+            Connection::new(raw.as_bytes(), ParserLimits::default(), timeouts());
+        let req = connection.read_request().unwrap().unwrap();
+        assert_eq!(req.body, body.as_bytes());
+        assert!(connection.read_request().unwrap().is_none());
+    }
+
+    #[test]
+    fn keeps_what_follows_a_request_for_the_next_one() {
+        // Both arrive in the first read
+        let raw = b"GET /first HTTP/1.1\r\n\r\nGET /second HTTP/1.1\r\n\r\n";
+        let mut connection =
+            Connection::new(&raw[..], ParserLimits::default(), timeouts());
+        let first = connection.read_request().unwrap().unwrap();
+        assert_eq!(first.resource.path(), "/first");
+        assert!(!connection.is_idle());
+        let second = connection.read_request().unwrap().unwrap();
+        assert_eq!(second.resource.path(), "/second");
+        assert!(connection.is_idle());
+        assert!(connection.read_request().unwrap().is_none());
+    }
+
+    #[test]
+    fn fails_on_a_request_cut_off_by_the_client() {
+        let raw = b"GET / HTTP/1.1\r\nHost: exa";
+        let mut connection =
+            Connection::new(&raw[..], ParserLimits::default(), timeouts());
+        let result = connection.read_request();
+        assert!(matches!(
+            result,
+            Err(ReadError::Parse(ParseError::Incomplete))
+        ));
+    }
+
+    #[test]
+    fn times_out_headers_trickling_in() {
+        let header = [b'x'; 200];
+        let stream = Trickle::new(
+            b"GET / HTTP/1.1\r\nX-Slow: ",
+            &header,
+            Duration::from_millis(5),
+        );
+        let mut connection = Connection::new(stream, ParserLimits::default(), timeouts());
+        let started = Instant::now();
+        let result = connection.read_request();
+        assert!(matches!(result, Err(ReadError::Timeout(Stage::Headers))));
+        assert!(started.elapsed() < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn times_out_bodies_arriving_too_slowly() {
+        let head = b"POST / HTTP/1.1\r\nContent-Length: 200\r\n\r\n";
+        let stream = Trickle::new(head, &[b'x'; 200], Duration::from_millis(5));
+        let mut connection = Connection::new(stream, ParserLimits::default(), timeouts());
+        let started = Instant::now();
+        let result = connection.read_request();
+        assert!(matches!(result, Err(ReadError::Timeout(Stage::Body))));
+        assert!(started.elapsed() < Duration::from_millis(500));
+    }
+}
@@ -0,0 +1,449 @@
+//! The event-loop backend: many connections served from a few threads
+//!
+//! Every thread runs its own mio event loop. The loops share the listening socket
+//! and each keeps the connections it accepted, reading requests as their bytes
+//! arrive and writing responses as fast as the socket drains, so an idle or slow
+//! client costs a little memory rather than a thread. Handlers still run on the
+//! loop thread: a slow handler holds up the other connections of its loop.
+
+use super::connection::{Connection, ReadError, Stage};
+use super::router::Router;
+use super::server::{self, Settings};
+use super::shutdown::ShutdownHandle;
+use http::http_response::{HttpResponse, ResponseReader};
+use mio::net::{TcpListener, TcpStream};
+use mio::{Events, Interest, Poll, Token};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const LISTENER: Token = Token(0);
+/// How often connections are checked for timeouts, and the loop for a shutdown
+const SWEEP_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Serve connections from `listener` on `threads` event loops until `shutdown`
+pub fn run(
+    listener: std::net::TcpListener,
+    settings: Settings,
+    router: &Arc<Router>,
+    threads: usize,
+    shutdown: &ShutdownHandle,
+) {
+    listener
+        .set_nonblocking(true)
+        .expect("Failed to make the listener non-blocking");
+    let loops: Vec<_> = (0..threads.max(1))
+        .map(|id| {
+            let listener = listener.try_clone().expect("Failed to clone the listener");
+            let router = Arc::clone(router);
+            let shutdown = shutdown.clone();
+            thread::Builder::new()
+                .name(format!("event-loop-{}", id))
+                .spawn(move || {
+                    let listener = TcpListener::from_std(listener);
+                    let event_loop = EventLoop::new(listener, settings, router, shutdown);
+                    if let Err(e) = event_loop.and_then(EventLoop::run) {
+                        eprintln!("Event loop failed: {}", e);
+                    }
+                })
+                .expect("Failed to spawn event loop thread")
+        })
+        .collect();
+    // The loops hold their own copies, which they close when shutting down
+    drop(listener);
+    for event_loop in loops {
+        let _ = event_loop.join();
+    }
+}
+
+struct EventLoop {
+    poll: Poll,
+    /// `None` once the loop stops accepting
+    listener: Option<TcpListener>,
+    settings: Settings,
+    router: Arc<Router>,
+    shutdown: ShutdownHandle,
+    clients: HashMap<Token, Client>,
+    next_token: usize,
+}
+
+impl EventLoop {
+    fn new(
+        mut listener: TcpListener,
+        settings: Settings,
+        router: Arc<Router>,
+        shutdown: ShutdownHandle,
+    ) -> io::Result<Self> {
+        let poll = Poll::new()?;
+        poll.registry()
+            .register(&mut listener, LISTENER, Interest::READABLE)?;
+        Ok(EventLoop {
+            poll,
+            listener: Some(listener),
+            settings,
+            router,
+            shutdown,
+            clients: HashMap::new(),
+            next_token: LISTENER.0 + 1,
+        })
+    }
+
+    fn run(mut self) -> io::Result<()> {
+        let mut events = Events::with_capacity(1024);
+        let mut last_sweep = Instant::now();
+        loop {
+            match self.poll.poll(&mut events, Some(SWEEP_INTERVAL)) {
+                Ok(()) => (),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+            for event in events.iter() {
+                match event.token() {
+                    LISTENER => self.accept(),
+                    token => self.ready(token),
+                }
+            }
+            if last_sweep.elapsed() >= SWEEP_INTERVAL {
+                let tokens: Vec<Token> = self.clients.keys().copied().collect();
+                for token in tokens {
+                    self.check_timeouts(token);
+                }
+                last_sweep = Instant::now();
+            }
+            if let Some(requested_at) = self.shutdown.requested_at() {
+                // Closing the listener turns new clients away rather than leaving
+                // them in the backlog
+                self.listener = None;
+                // Connections between requests go now, the rest once they are done
+                self.clients.retain(|_, client| !client.is_idle());
+                if self.clients.is_empty()
+                    || requested_at.elapsed() >= self.settings.drain_timeout
+                {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Take every connection that is waiting. The other loops are woken too and
+    /// race for them, whoever loses just finds none left.
+    fn accept(&mut self) {
+        let listener = match &self.listener {
+            Some(listener) if !self.shutdown.is_shutdown() => listener,
+            _ => return,
+        };
+        loop {
+            let (mut stream, addr) = match listener.accept() {
+                Ok(accepted) => accepted,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    eprintln!("Cannot accept connection: {}", e);
+                    return;
+                }
+            };
+            println!("Connection established with {}", addr);
+            let token = Token(self.next_token);
+            self.next_token += 1;
+            let interest = Interest::READABLE | Interest::WRITABLE;
+            if let Err(e) = self.poll.registry().register(&mut stream, token, interest) {
+                eprintln!("Cannot watch connection: {}", e);
+                continue;
+            }
+            self.clients
+                .insert(token, Client::new(stream, &self.settings));
+        }
+    }
+
+    /// The connection behind `token` can be read from or written to
+    fn ready(&mut self, token: Token) {
+        let open = match self.clients.get_mut(&token) {
+            Some(client) => client.drive(&self.settings, &self.router, &self.shutdown),
+            None => return,
+        };
+        // Dropping the stream closes it and takes it out of the poll
+        if !open {
+            self.clients.remove(&token);
+        }
+    }
+
+    /// Close the connection behind `token` if the client is taking too long, after
+    /// telling it so if it was in the middle of a request
+    fn check_timeouts(&mut self, token: Token) {
+        let timeouts = &self.settings.timeouts;
+        let client = match self.clients.get_mut(&token) {
+            Some(client) => client,
+            None => return,
+        };
+        let open = if client.response.is_some() {
+            client.last_write.elapsed() < timeouts.write
+        } else {
+            match client.connection.timed_out() {
+                None => true,
+                Some(Stage::Idle) => false,
+                Some(stage) => {
+                    eprintln!("Timed out reading the request ({:?})", stage);
+                    client.answer(server::request_timeout(), false);
+                    client.drive(&self.settings, &self.router, &self.shutdown)
+                }
+            }
+        };
+        if !open {
+            self.clients.remove(&token);
+        }
+    }
+}
+
+/// A connection and where it is in the request/response cycle
+struct Client {
+    connection: Connection<TcpStream>,
+    /// The response being sent, if any
+    response: Option<ResponseReader>,
+    /// Whether to close the connection once `response` is out
+    close_after: bool,
+    served: usize,
+    /// When the response last made progress
+    last_write: Instant,
+}
+
+impl Client {
+    fn new(stream: TcpStream, settings: &Settings) -> Self {
+        Client {
+            connection: Connection::new(stream, settings.limits, settings.timeouts),
+            response: None,
+            close_after: false,
+            served: 0,
+            last_write: Instant::now(),
+        }
+    }
+
+    /// Start sending `response`
+    fn answer(&mut self, response: HttpResponse, keep_alive: bool) {
+        self.response = Some(response.into_reader());
+        self.close_after = !keep_alive;
+        self.last_write = Instant::now();
+    }
+
+    /// Whether the connection is between requests, with no response to send
+    fn is_idle(&self) -> bool {
+        self.response.is_none() && self.connection.is_idle()
+    }
+
+    /// Get as far as the socket allows. Requests are answered one at a time, so
+    /// pipelined ones are answered in order, and no more are read while a response
+    /// is still waiting to be sent. Returns false once the connection should close.
+    fn drive(
+        &mut self,
+        settings: &Settings,
+        router: &Router,
+        shutdown: &ShutdownHandle,
+    ) -> bool {
+        loop {
+            if let Some(response) = &mut self.response {
+                match send(response, self.connection.stream(), &mut self.last_write) {
+                    Ok(true) if self.close_after => return false,
+                    Ok(true) => self.response = None,
+                    Ok(false) => return true,
+                    Err(e) => {
+                        eprintln!("Cannot write to socket: {}", e);
+                        return false;
+                    }
+                }
+            }
+            let (response, keep_alive) = match self.connection.read_request() {
+                Ok(Some(req)) => {
+                    self.served += 1;
+                    server::respond(router, req, self.served, settings, shutdown)
+                }
+                Ok(None) => return false,
+                Err(ReadError::Parse(e)) => {
+                    eprintln!("Error parsing HTTP Request: {}", e);
+                    (server::rejection(&e), false)
+                }
+                Err(ReadError::Timeout(stage)) => {
+                    eprintln!("Timed out reading the request ({:?})", stage);
+                    (server::request_timeout(), false)
+                }
+                Err(ReadError::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return true
+                }
+                Err(ReadError::Io(e)) => {
+                    eprintln!("Cannot read from socket: {}", e);
+                    return false;
+                }
+            };
+            self.answer(response, keep_alive);
+        }
+    }
+}
+
+/// Write as much of `response` as the socket takes, noting the time in
+/// `last_write` if any of it went out. Returns true once all of it has been
+/// written.
+fn send(
+    response: &mut ResponseReader,
+    mut stream: &TcpStream,
+    last_write: &mut Instant,
+) -> io::Result<bool> {
+    loop {
+        let buf = response.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(true);
+        }
+        match stream.write(buf) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(written) => {
+                response.consume(written);
+                *last_write = Instant::now();
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::Params;
+    use crate::server::tests::start;
+    use crate::server::{Backend, Server, Timeouts};
+    use http::http_request::HttpRequest;
+    use std::io::Read;
+    use std::net::{SocketAddr, TcpStream};
+
+    /// Bytes in the response to `/large`, more than the socket buffers hold
+    const LARGE: usize = 16 * 1024 * 1024;
+
+    /// An event-loop server answering GET `/large` with `LARGE` bytes and every
+    /// other GET with the path it was asked for
+    fn server(threads: usize, timeouts: Timeouts) -> Server<'static> {
+        let mut router = Router::new();
+        router
+            .get("/large", |_: &HttpRequest, _: &Params| {
+                HttpResponse::builder().body(vec![b'x'; LARGE])
+            })
+            .unwrap()
+            .get("/*path", |req: &HttpRequest, _: &Params| {
+                HttpResponse::builder().body(req.resource.path().to_string())
+            })
+            .unwrap();
+        Server::new("127.0.0.1:0")
+            .backend(Backend::EventLoop)
+            .threads(threads)
+            .timeouts(timeouts)
+            .router(router)
+    }
+
+    fn connect(addr: SocketAddr) -> TcpStream {
+        let client = TcpStream::connect(addr).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client
+    }
+
+    /// Send `raw` and read until the server closes the connection
+    fn exchange(addr: SocketAddr, raw: &[u8]) -> String {
+        let mut client = connect(addr);
+        client.write_all(raw).unwrap();
+        let mut answer = String::new();
+        client.read_to_string(&mut answer).unwrap();
+        answer
+    }
+
+    #[test]
+    fn answers_pipelined_requests_in_order() {
+        let (addr, shutdown, running) = start(server(1, Timeouts::default()));
+        let answer = exchange(
+            addr,
+            b"GET /first HTTP/1.1\r\n\r\nGET /second HTTP/1.1\r\n\r\n\
+              GET /third HTTP/1.1\r\nConnection: close\r\n\r\n",
+        );
+        let bodies: Vec<&str> = answer
+            .split("HTTP/1.1 200 OK")
+            .skip(1)
+            .map(|answer| answer.rsplit("\r\n\r\n").next().unwrap())
+            .collect();
+        assert_eq!(bodies, ["/first", "/second", "/third"]);
+        shutdown.shutdown();
+        running.join().unwrap();
+    }
+
+    #[test]
+    fn serves_clients_whichever_loop_accepts_them() {
+        let (addr, shutdown, running) = start(server(4, Timeouts::default()));
+        let clients: Vec<_> = (0..32)
+            .map(|i| {
+                thread::spawn(move || {
+                    let raw = format!("GET /{} HTTP/1.1\r\nConnection: close\r\n\r\n", i);
+                    let answer = exchange(addr, raw.as_bytes());
+                    assert!(answer.starts_with("HTTP/1.1 200 OK\r\n"), "{}", answer);
+                    assert!(answer.ends_with(&format!("\r\n\r\n/{}", i)));
+                })
+            })
+            .collect();
+        for client in clients {
+            client.join().unwrap();
+        }
+        shutdown.shutdown();
+        running.join().unwrap();
+    }
+
+    #[test]
+    fn finishes_responses_the_socket_could_not_take_at_once() {
+        let (addr, shutdown, running) = start(server(1, Timeouts::default()));
+        let mut client = connect(addr);
+        client
+            .write_all(b"GET /large HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        // The buffers fill up while the client isn't reading, leaving the rest for
+        // once it is
+        thread::sleep(Duration::from_millis(200));
+        let mut answer = Vec::new();
+        client.read_to_end(&mut answer).unwrap();
+        let head = answer.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        assert!(answer.starts_with(b"HTTP/1.1 200 OK\r\n"));
+        assert_eq!(answer.len() - head, LARGE);
+        assert!(answer[head..].iter().all(|&b| b == b'x'));
+        shutdown.shutdown();
+        running.join().unwrap();
+    }
+
+    #[test]
+    fn answers_408_to_headers_that_stop_coming() {
+        let timeouts = Timeouts {
+            header_read: Duration::from_millis(100),
+            ..Timeouts::default()
+        };
+        let (addr, shutdown, running) = start(server(1, timeouts));
+        let started = Instant::now();
+        let answer = exchange(addr, b"GET / HTTP/1.1\r\nHost: exa");
+        assert!(
+            answer.starts_with("HTTP/1.1 408 Request Timeout\r\n"),
+            "{}",
+            answer
+        );
+        assert!(started.elapsed() < Duration::from_secs(2));
+        shutdown.shutdown();
+        running.join().unwrap();
+    }
+
+    #[test]
+    fn closes_connections_idle_for_too_long() {
+        let timeouts = Timeouts {
+            idle: Duration::from_millis(100),
+            ..Timeouts::default()
+        };
+        let (addr, shutdown, running) = start(server(1, timeouts));
+        let started = Instant::now();
+        let answer = exchange(addr, b"GET /once HTTP/1.1\r\n\r\n");
+        assert!(answer.ends_with("\r\n\r\n/once"), "{}", answer);
+        assert!(started.elapsed() < Duration::from_secs(2));
+        shutdown.shutdown();
+        running.join().unwrap();
+    }
+}
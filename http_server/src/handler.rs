@@ -1,26 +1,223 @@
-use http::{http_request::HttpRequest, http_response::HttpResponse};
+use http::http_request::HttpRequest;
+use http::http_response::{Body, HttpResponse};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use http::headers::HeaderMap;
+use http::status::StatusCode;
 use std::env;
-use std::fs;
+use std::fs::{self, File};
+
+use super::router::Params;
 
 /// Serves 404 page
 pub struct PageNotFound;
-/// Serves static resources
-pub struct StaticHandler;
+/// Serves static resources from a public folder
+pub struct StaticHandler {
+    public: String,
+}
 /// Serves JSON data
 pub struct WebServiceHandler;
 
-pub trait Handler{
-    /// Process the incoming request
-    fn handle(req: &HttpRequest) -> HttpResponse; 
-    /// Load a resource from PUBLIC_DIR or {root}/public folder and return it
-    fn load(file: &str) -> Option<String> {
-        let default = format!("{}/public", env!("CARGO_MANIFEST_DIR"));
-        let public = env::var("PUBLIC_DIR").unwrap_or(default);
-        let full_path = format!("{}/{}", public,file);
+/// Answers the requests routed to it. Handlers are shared by every thread serving
+/// requests, so any state they hold has to be `Sync`.
+pub trait Handler: Send + Sync {
+    /// Process the incoming request, given the parameters its route picked out
+    fn handle(&self, req: &HttpRequest, params: &Params) -> HttpResponse;
+}
+
+/// Closures taking the request and its parameters work as handlers
+impl<F> Handler for F
+where
+    F: Fn(&HttpRequest, &Params) -> HttpResponse + Send + Sync,
+{
+    fn handle(&self, req: &HttpRequest, params: &Params) -> HttpResponse {
+        self(req, params)
+    }
+}
+
+/// The PUBLIC_DIR folder, or {root}/public if it isn't set
+pub fn public_dir() -> String {
+    let default = format!("{}/public", env!("CARGO_MANIFEST_DIR"));
+    env::var("PUBLIC_DIR").unwrap_or(default)
+}
+
+/// Load a resource from the public folder and return it
+pub fn load(file: &str) -> Option<String> {
+    let full_path = format!("{}/{}", public_dir(), file);
+
+    let file_contents = fs::read_to_string(full_path);
+    file_contents.ok()
+}
+
+impl Handler for PageNotFound {
+    fn handle(&self, _req: &HttpRequest, _params: &Params) -> HttpResponse {
+        HttpResponse::new(StatusCode::NOT_FOUND, None, load("404.html"))
+    }
+}
+
+impl Handler for StaticHandler {
+    fn handle(&self, req: &HttpRequest, params: &Params) -> HttpResponse {
+        // A decoded segment may still hide a path separator, e.g. `..%2Fsecret`
+        let segments = req.resource.path_segments();
+        if segments.iter().any(|segment| segment.contains(['/', '\\'])) {
+            return self.not_found();
+        }
+        match params.get("path").unwrap_or_default() {
+            "" => HttpResponse::new(StatusCode::OK, None, self.read("index.html")),
+            "health" => HttpResponse::new(StatusCode::OK, None, self.read("health.html")),
+            path => match self.open(path) {
+                Some((file, length)) => HttpResponse::builder()
+                    .header("Content-type", content_type(path))
+                    .body(Body::from_reader(file, Some(length))),
+                None => self.not_found(),
+            },
+        }
+    }
+}
+
+impl StaticHandler {
+    /// Serve the files below the `public` folder
+    pub fn new(public: impl Into<String>) -> Self {
+        StaticHandler {
+            public: public.into(),
+        }
+    }
+
+    fn read(&self, file: &str) -> Option<String> {
+        fs::read_to_string(format!("{}/{}", self.public, file)).ok()
+    }
+
+    /// The public folder's own 404 page
+    fn not_found(&self) -> HttpResponse {
+        HttpResponse::new(StatusCode::NOT_FOUND, None, self.read("404.html"))
+    }
+
+    /// Open a regular file below the public folder along with its size, so that it
+    /// can be streamed rather than read into memory
+    fn open(&self, file: &str) -> Option<(File, u64)> {
+        let file = File::open(format!("{}/{}", self.public, file)).ok()?;
+        let metadata = file.metadata().ok()?;
+        if metadata.is_file() {
+            Some((file, metadata.len()))
+        } else {
+            None
+        }
+    }
+}
+
+/// Media type to announce for a static file, going by its extension
+fn content_type(path: &str) -> &'static str {
+    let extension = path.rsplit_once('.').map_or("", |(_, extension)| extension);
+    match extension.to_ascii_lowercase().as_str() {
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "js" => "text/javascript",
+        "json" => "application/json",
+        "txt" => "text/plain",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+/// An order as stored in {root}/data/orders.json
+#[derive(Serialize, Deserialize)]
+pub struct OrderStatus {
+    order_id: i32,
+    order_date: String,
+    order_status: String,
+}
+
+impl WebServiceHandler {
+    /// Load orders from DATA_PATH or the {root}/data folder
+    fn load_json() -> Option<Vec<OrderStatus>> {
+        let default = format!("{}/data", env!("CARGO_MANIFEST_DIR"));
+        let data_path = env::var("DATA_PATH").unwrap_or(default);
+        let full_path = format!("{}/{}", data_path, "orders.json");
+
+        let json_contents = fs::read_to_string(full_path).ok()?;
+        serde_json::from_str(&json_contents).ok()
+    }
+}
+
+impl Handler for WebServiceHandler {
+    /// localhost:3000/api/shipping/orders lists all orders and
+    /// localhost:3000/api/shipping/orders/:id the one with that id
+    fn handle(&self, _req: &HttpRequest, params: &Params) -> HttpResponse {
+        let orders = match Self::load_json() {
+            Some(orders) => orders,
+            None => {
+                return HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR, None, None)
+            }
+        };
+        let body = match params.get("id") {
+            None => serde_json::to_string(&orders).ok(),
+            Some(id) => {
+                let order = id
+                    .parse()
+                    .ok()
+                    .and_then(|id: i32| orders.iter().find(|order| order.order_id == id));
+                match order {
+                    Some(order) => serde_json::to_string(order).ok(),
+                    None => {
+                        let body = load("404.html");
+                        return HttpResponse::new(StatusCode::NOT_FOUND, None, body);
+                    }
+                }
+            }
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-type", "application/json");
+        HttpResponse::new(StatusCode::OK, Some(headers), body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::Router;
+    use std::convert::TryFrom;
+    use std::process;
+
+    fn get(router: &Router, target: &str) -> (StatusCode, String) {
+        let raw = format!("GET {} HTTP/1.1\r\n\r\n", target);
+        let response = router.route(&mut HttpRequest::try_from(raw.as_bytes()).unwrap());
+        let body = response.body().as_bytes().unwrap_or_default();
+        (response.status(), String::from_utf8_lossy(body).into_owned())
+    }
+
+    #[test]
+    fn routes_to_stateful_handlers_and_closures_alike() {
+        let public = env::temp_dir().join(format!("static-handler-{}", process::id()));
+        fs::create_dir_all(&public).unwrap();
+        fs::write(public.join("index.html"), "home").unwrap();
+        fs::write(public.join("404.html"), "not here").unwrap();
+
+        // The router keeps both as `Box<dyn Handler>`
+        let greeting = String::from("hello");
+        let mut router = Router::new();
+        router
+            .get("/hello/:name", move |_: &HttpRequest, params: &Params| {
+                let name = params.get("name").unwrap_or_default();
+                HttpResponse::builder().body(format!("{} {}", greeting, name))
+            })
+            .unwrap()
+            .get("/*path", StaticHandler::new(public.to_string_lossy()))
+            .unwrap();
+        assert_eq!(get(&router, "/hello/you"), (StatusCode::OK, "hello you".into()));
+        assert_eq!(get(&router, "/"), (StatusCode::OK, "home".into()));
+        // Its own 404 page, not that of PUBLIC_DIR
+        let missing = (StatusCode::NOT_FOUND, "not here".into());
+        assert_eq!(get(&router, "/missing.txt"), missing);
+        assert_eq!(get(&router, "/..%2Fsecret"), missing);
 
-        let file_contents = fs::read_to_string(full_path);
-        file_contents.ok()
+        fs::remove_dir_all(&public).unwrap();
     }
-}
\ No newline at end of file
+}
@@ -6,12 +6,67 @@
 //! 
 //! This crate will bind to a socket and listen for incoming connections
 
+mod connection;
+mod event_loop;
+mod pool;
 mod router;
 mod server;
+mod shutdown;
 mod handler;
-use server::Server;
+mod middleware;
+use handler::{StaticHandler, WebServiceHandler};
+use http::http_request::HttpRequest;
+use http::parser::ParserLimits;
+use middleware::{Next, RequestLog};
+use router::{RouteError, Router};
+use server::{Backend, Server, Timeouts};
+use std::env;
+use std::time::Duration;
+/// Every route the server answers. Anything else gets a 404.
+fn routes() -> Result<Router, RouteError> {
+    let mut router = Router::new();
+    router.wrap(RequestLog);
+    router
+        .group("/api/shipping")
+        .wrap(|req: &mut HttpRequest, next: Next<'_>| {
+            let mut response = next.run(req);
+            // Order statuses change all the time
+            response.headers_mut().insert("Cache-Control", "no-store");
+            response
+        })
+        .get("/orders", WebServiceHandler)?
+        .get("/orders/:id", WebServiceHandler)?;
+    router.get("/*path", StaticHandler::new(handler::public_dir()))?;
+    Ok(router)
+}
+
 fn main(){
     let addr = "localhost:3000";
-    let server = Server::new(addr);    
+    // Nothing we serve takes an upload bigger than this
+    let limits = ParserLimits {
+        max_body_size: 1024 * 1024,
+        ..ParserLimits::default()
+    };
+    // Thousands of idle keep-alive clients are cheaper on the event loop
+    let backend = match env::var("SERVER_BACKEND").as_deref() {
+        Ok("event-loop") => Backend::EventLoop,
+        _ => Backend::Threads,
+    };
+    let router = routes().expect("Invalid route table");
+    let server = Server::new(addr)
+        .router(router)
+        .backend(backend)
+        .limits(limits)
+        .timeouts(Timeouts {
+            idle: Duration::from_secs(10),
+            ..Timeouts::default()
+        })
+        .max_requests(1000)
+        .threads(16)
+        .queue_depth(128)
+        .drain_timeout(Duration::from_secs(15));
+    if let Err(e) = shutdown::on_signals(server.shutdown_handle()) {
+        eprintln!("Cannot handle signals, stop the server by killing it: {}", e);
+    }
     server.run();
 }
\ No newline at end of file
@@ -0,0 +1,76 @@
+//! Logic that wraps request handling without touching the handlers
+//!
+//! A `Middleware` sits between the connection and the handler. It gets the request
+//! first and may look at it or change it. It can then call `Next::run` to pass the
+//! request on, and look at or change the response that comes back. Or it can
+//! answer the request itself and not call `next` at all. Middleware registered
+//! with `Router::wrap` runs for every request, before routing. Middleware of a
+//! route group only runs for the routes of that group, after routing, including
+//! the OPTIONS and 405 answers given for their paths. Either way it runs in the
+//! order it was added, the first one outermost.
+use http::http_request::HttpRequest;
+use http::http_response::HttpResponse;
+use std::sync::Arc;
+use std::time::Instant;
+
+pub trait Middleware: Send + Sync {
+    /// Handle `req`, usually by passing it on with `next.run(req)`
+    fn handle(&self, req: &mut HttpRequest, next: Next<'_>) -> HttpResponse;
+}
+
+/// Closures taking the request and `Next` work as middleware
+impl<F> Middleware for F
+where
+    F: Fn(&mut HttpRequest, Next<'_>) -> HttpResponse + Send + Sync,
+{
+    fn handle(&self, req: &mut HttpRequest, next: Next<'_>) -> HttpResponse {
+        self(req, next)
+    }
+}
+
+/// The rest of the chain: the middleware still to run and what answers the request
+/// once they all have
+pub struct Next<'a> {
+    middleware: &'a [Arc<dyn Middleware>],
+    endpoint: &'a dyn Fn(&mut HttpRequest) -> HttpResponse,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(
+        middleware: &'a [Arc<dyn Middleware>],
+        endpoint: &'a dyn Fn(&mut HttpRequest) -> HttpResponse,
+    ) -> Self {
+        Next {
+            middleware,
+            endpoint,
+        }
+    }
+
+    /// Pass `req` on and get the response back
+    pub fn run(self, req: &mut HttpRequest) -> HttpResponse {
+        match self.middleware.split_first() {
+            Some((first, rest)) => first.handle(req, Next::new(rest, self.endpoint)),
+            None => (self.endpoint)(req),
+        }
+    }
+}
+
+/// Logs every request along with the status it got and how long that took
+pub struct RequestLog;
+
+impl Middleware for RequestLog {
+    fn handle(&self, req: &mut HttpRequest, next: Next<'_>) -> HttpResponse {
+        let started = Instant::now();
+        let method = req.method.clone();
+        let target = req.resource.path_and_query();
+        let response = next.run(req);
+        println!(
+            "{} {} {} ({:?})",
+            method,
+            target,
+            response.status().as_u16(),
+            started.elapsed()
+        );
+        response
+    }
+}
@@ -0,0 +1,157 @@
+//! A fixed number of worker threads fed from a bounded queue
+//!
+//! The accept loop hands each connection to `WorkerPool::submit` and goes straight
+//! back to accepting. When every worker is busy and the queue is full, `submit`
+//! gives the connection back so the caller can turn it away, rather than letting
+//! the backlog grow without bound.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Worker threads that each run `handler` on items taken from a shared queue
+pub struct WorkerPool<T> {
+    sender: Option<SyncSender<T>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl<T: Send + 'static> WorkerPool<T> {
+    /// Start `threads` workers, at least one. At most `queue_depth` items wait for a
+    /// free worker; with a depth of 0 an item is only accepted if a worker is idle.
+    pub fn new<F>(threads: usize, queue_depth: usize, handler: F) -> Self
+    where
+        F: Fn(T) + Send + Sync + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel(queue_depth);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let handler = Arc::new(handler);
+        let workers = (0..threads.max(1))
+            .map(|id| {
+                let receiver = Arc::clone(&receiver);
+                let handler = Arc::clone(&handler);
+                thread::Builder::new()
+                    .name(format!("worker-{}", id))
+                    .spawn(move || work(&receiver, &*handler))
+                    .expect("Failed to spawn worker thread")
+            })
+            .collect();
+        WorkerPool {
+            sender: Some(sender),
+            workers,
+        }
+    }
+
+    /// Queue `item` for the next free worker, or hand it back if the queue is full
+    pub fn submit(&self, item: T) -> Result<(), T> {
+        let sender = self.sender.as_ref().expect("pool is running");
+        match sender.try_send(item) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(item)) | Err(TrySendError::Disconnected(item)) => {
+                Err(item)
+            }
+        }
+    }
+
+    /// Let the workers finish what is queued, waiting for them until `deadline`.
+    /// Returns how many were still busy then; they are left to run.
+    pub fn shutdown(mut self, deadline: Instant) -> usize {
+        drop(self.sender.take());
+        let mut workers = std::mem::take(&mut self.workers);
+        while Instant::now() < deadline
+            && workers.iter().any(|worker| !worker.is_finished())
+        {
+            thread::sleep(Duration::from_millis(10));
+        }
+        workers.retain(|worker| !worker.is_finished());
+        workers.len()
+    }
+}
+
+impl<T> Drop for WorkerPool<T> {
+    /// Let the workers finish what is queued, then wait for them
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Run `handler` on queued items until the pool is dropped
+fn work<T>(receiver: &Mutex<Receiver<T>>, handler: &(dyn Fn(T) + Sync)) {
+    loop {
+        // The lock is only held while waiting, so the others can take the next item
+        // as soon as this one is handed out
+        let item = match receiver.lock() {
+            Ok(receiver) => receiver.recv(),
+            Err(poisoned) => poisoned.into_inner().recv(),
+        };
+        let item = match item {
+            Ok(item) => item,
+            Err(_) => return,
+        };
+        // A panicking handler costs one connection, not a worker
+        if panic::catch_unwind(AssertUnwindSafe(|| handler(item))).is_err() {
+            let name = thread::current().name().map(str::to_string);
+            eprintln!("Handler panicked on {}", name.as_deref().unwrap_or("a worker"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::Sender;
+
+    #[test]
+    fn hands_items_back_once_the_queue_is_full() {
+        let (started, on_start) = mpsc::channel();
+        let (release, gate) = mpsc::channel::<()>();
+        let gate = Mutex::new(gate);
+        let started: Mutex<Sender<u32>> = Mutex::new(started);
+        let pool = WorkerPool::new(1, 1, move |item| {
+            started.lock().unwrap().send(item).unwrap();
+            let _ = gate.lock().unwrap().recv();
+        });
+        assert_eq!(pool.submit(1), Ok(()));
+        // Wait for the worker to be busy with it, so only the queue is left
+        assert_eq!(on_start.recv().unwrap(), 1);
+        assert_eq!(pool.submit(2), Ok(()));
+        assert_eq!(pool.submit(3), Err(3));
+
+        drop(release);
+        assert_eq!(pool.shutdown(Instant::now() + Duration::from_secs(5)), 0);
+        assert_eq!(on_start.try_iter().collect::<Vec<_>>(), [2]);
+    }
+
+    #[test]
+    fn shutdown_drains_what_is_queued() {
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&handled);
+        let pool = WorkerPool::new(1, 8, move |item| {
+            thread::sleep(Duration::from_millis(5));
+            seen.lock().unwrap().push(item);
+        });
+        for item in 1..=5 {
+            assert_eq!(pool.submit(item), Ok(()));
+        }
+        assert_eq!(pool.shutdown(Instant::now() + Duration::from_secs(5)), 0);
+        assert_eq!(*handled.lock().unwrap(), [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn shutdown_gives_up_on_workers_busy_past_the_deadline() {
+        let (release, gate) = mpsc::channel::<()>();
+        let gate = Mutex::new(gate);
+        let pool = WorkerPool::new(2, 0, move |_: ()| {
+            let _ = gate.lock().unwrap().recv();
+        });
+        while pool.submit(()).is_err() {
+            thread::yield_now();
+        }
+        assert_eq!(pool.shutdown(Instant::now() + Duration::from_millis(50)), 1);
+        drop(release);
+    }
+}
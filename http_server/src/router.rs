@@ -1,39 +1,707 @@
-use std::net::TcpStream;
-
+//! The route table that maps requests to handlers
+//!
+//! Routes are registered at startup with a method and a path pattern such as
+//! `/api/orders/:id` or `/static/*path`. A `:name` segment matches any one
+//! non-empty segment and a trailing `*name` matches the rest of the path, however
+//! many segments that is, including none. Either is handed to the handler under its
+//! name. When several routes match, the most specific one wins: going left to
+//! right, a literal segment beats a parameter, a parameter beats a wildcard, and
+//! a pattern that ends beats a wildcard.
+use http::status::StatusCode;
+use http::uri::TargetForm;
 use http::{http_request::*, http_response::HttpResponse};
+use std::fmt;
+
+use super::handler::{Handler, PageNotFound};
+use super::middleware::{Middleware, Next};
+use std::sync::Arc;
+
+/// Path parameters picked out by a route, by name
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Params {
+    pairs: Vec<(String, String)>,
+}
+
+impl Params {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.pairs
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Why a route couldn't be added
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RouteError {
+    /// The pattern isn't well formed
+    InvalidPattern {
+        pattern: String,
+        reason: &'static str,
+    },
+    /// A route for the same method already matches exactly the same paths
+    Conflict {
+        method: String,
+        pattern: String,
+        existing: String,
+    },
+}
+
+impl fmt::Display for RouteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RouteError::InvalidPattern { pattern, reason } => {
+                write!(f, "invalid route pattern {}: {}", pattern, reason)
+            }
+            RouteError::Conflict {
+                method,
+                pattern,
+                existing,
+            } => write!(
+                f,
+                "{} {} conflicts with {} {}",
+                method, pattern, method, existing
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RouteError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Param(String),
+    Wildcard(String),
+}
+
+/// A parsed path pattern
+#[derive(Debug, Clone)]
+struct Pattern {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+impl Pattern {
+    fn parse(pattern: &str) -> Result<Self, RouteError> {
+        let invalid = |reason| RouteError::InvalidPattern {
+            pattern: pattern.to_string(),
+            reason,
+        };
+        let path = pattern
+            .strip_prefix('/')
+            .ok_or_else(|| invalid("must start with /"))?;
+        let parts: Vec<&str> = path.split('/').collect();
+        let mut segments = Vec::with_capacity(parts.len());
+        for (index, part) in parts.iter().enumerate() {
+            let segment = if let Some(name) = part.strip_prefix(':') {
+                Segment::Param(name.to_string())
+            } else if let Some(name) = part.strip_prefix('*') {
+                if index + 1 != parts.len() {
+                    return Err(invalid("a wildcard must be the last segment"));
+                }
+                Segment::Wildcard(name.to_string())
+            } else {
+                Segment::Literal(part.to_string())
+            };
+            if let Segment::Param(name) | Segment::Wildcard(name) = &segment {
+                if name.is_empty() {
+                    return Err(invalid("parameters need a name"));
+                }
+                if segments.iter().any(|other| match other {
+                    Segment::Param(other) | Segment::Wildcard(other) => other == name,
+                    Segment::Literal(_) => false,
+                }) {
+                    return Err(invalid("parameter names must be unique"));
+                }
+            }
+            segments.push(segment);
+        }
+        Ok(Pattern {
+            source: pattern.to_string(),
+            segments,
+        })
+    }
+
+    /// The parameters if the pattern matches the decoded path `segments`
+    fn matches(&self, segments: &[String]) -> Option<Params> {
+        let mut params = Params::default();
+        for (index, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::Literal(literal) => {
+                    if segments.get(index) != Some(literal) {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => {
+                    let value = segments.get(index).filter(|value| !value.is_empty())?;
+                    params.pairs.push((name.clone(), value.clone()));
+                }
+                Segment::Wildcard(name) => {
+                    let rest = segments.get(index..).unwrap_or_default().join("/");
+                    params.pairs.push((name.clone(), rest));
+                    return Some(params);
+                }
+            }
+        }
+        if segments.len() == self.segments.len() {
+            Some(params)
+        } else {
+            None
+        }
+    }
+
+    /// Sorts patterns by how specific they are. A pattern's end ranks above a
+    /// wildcard, which only ever compares with a pattern that ends there.
+    fn specificity(&self) -> Vec<u8> {
+        let mut ranks: Vec<u8> = self
+            .segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(_) => 3,
+                Segment::Param(_) => 2,
+                Segment::Wildcard(_) => 0,
+            })
+            .collect();
+        ranks.push(1);
+        ranks
+    }
 
-use super::handler::{PageNotFound, StaticHandler, WebServiceHandler};
-/// Route a incoming request to the appropriate handler
-/// Also call the `send_response` method on the stream to send back a response
-pub struct Router;
+    /// Whether both patterns match exactly the same paths
+    fn same_paths(&self, other: &Pattern) -> bool {
+        self.segments.len() == other.segments.len()
+            && self
+                .segments
+                .iter()
+                .zip(&other.segments)
+                .all(|pair| match pair {
+                    (Segment::Literal(a), Segment::Literal(b)) => a == b,
+                    (Segment::Param(_), Segment::Param(_)) => true,
+                    (Segment::Wildcard(_), Segment::Wildcard(_)) => true,
+                    _ => false,
+                })
+    }
+}
+
+struct Route {
+    method: Method,
+    pattern: Pattern,
+    handler: Box<dyn Handler>,
+    /// That of the group the route was added to
+    middleware: Vec<Arc<dyn Middleware>>,
+}
+
+/// Routes an incoming request to the appropriate handler and hands back its response.
+/// Sending it is up to the caller, which knows about the connection.
+pub struct Router {
+    routes: Vec<Route>,
+    not_found: Box<dyn Handler>,
+    /// Runs for every request, ahead of routing
+    middleware: Vec<Arc<dyn Middleware>>,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Router {
+            routes: Vec::new(),
+            not_found: Box::new(PageNotFound),
+            middleware: Vec::new(),
+        }
+    }
+}
 
 impl Router {
-    pub fn route(req: HttpRequest, mut stream: &TcpStream) {
-        let route = req.get_url();
-        let method = req.method;
-        match method {
-            Method::Get => {
-                // A GET request maybe for a static page or a web service
-                // Web services start with the keyword "api"
-                let api_switch =
-                    if route.split("/").into_iter().skip(1).next() == Some("api") {
-                        true
-                    } else {
-                        false
-                    };
-                let response: HttpResponse = if api_switch {
-                    WebServiceHandler::handle(&req)
-                } else {
-                    StaticHandler::handle(&req)
-                };
-                response.send_response(&mut stream);
+    /// A router without any routes, answering everything with `PageNotFound`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Send `method` requests for paths matching `pattern` to `handler`. Fails if
+    /// the pattern is malformed or another route already covers the same paths.
+    pub fn add(
+        &mut self,
+        method: Method,
+        pattern: &str,
+        handler: impl Handler + 'static,
+    ) -> Result<&mut Self, RouteError> {
+        self.insert(method, pattern, Box::new(handler), Vec::new())?;
+        Ok(self)
+    }
+
+    /// Shorthand for `add(Method::Get, ..)`
+    pub fn get(
+        &mut self,
+        pattern: &str,
+        handler: impl Handler + 'static,
+    ) -> Result<&mut Self, RouteError> {
+        self.add(Method::Get, pattern, handler)
+    }
+
+    /// Run `middleware` for every request, including those no route matches. It
+    /// runs before routing, so it may change what the request is routed to.
+    pub fn wrap(&mut self, middleware: impl Middleware + 'static) -> &mut Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Start a group of routes whose patterns all begin with `prefix` and which
+    /// share middleware of their own
+    pub fn group(&mut self, prefix: &str) -> Group<'_> {
+        Group {
+            router: self,
+            prefix: prefix.trim_end_matches('/').to_string(),
+            middleware: Vec::new(),
+        }
+    }
+
+    fn insert(
+        &mut self,
+        method: Method,
+        pattern: &str,
+        handler: Box<dyn Handler>,
+        middleware: Vec<Arc<dyn Middleware>>,
+    ) -> Result<(), RouteError> {
+        let pattern = Pattern::parse(pattern)?;
+        let existing = self
+            .routes
+            .iter()
+            .find(|route| route.method == method && route.pattern.same_paths(&pattern));
+        if let Some(existing) = existing {
+            return Err(RouteError::Conflict {
+                method: method.as_str().to_string(),
+                pattern: pattern.source,
+                existing: existing.pattern.source.clone(),
+            });
+        }
+        self.routes.push(Route {
+            method,
+            pattern,
+            handler,
+            middleware,
+        });
+        Ok(())
+    }
+
+    /// Answer `req` with the handler of the best matching route, passing it
+    /// through the middleware on the way. A path that matches routes for other
+    /// methods only gets 405 Method Not Allowed, except that OPTIONS is answered
+    /// with the methods the path allows and HEAD falls back on the GET route.
+    /// Those answers go through the middleware of the most specific route too.
+    /// `OPTIONS *` is about the server as a whole and lists every method routed.
+    pub fn route(&self, req: &mut HttpRequest) -> HttpResponse {
+        Next::new(&self.middleware, &|req| self.dispatch(req)).run(req)
+    }
+
+    fn dispatch(&self, req: &mut HttpRequest) -> HttpResponse {
+        // Not a path, so no pattern should match it, `/*path` included
+        if req.resource.form() == TargetForm::Asterisk {
+            return HttpResponse::builder()
+                .status(StatusCode::NO_CONTENT)
+                .header("Allow", Self::allow(&self.routes))
+                .build();
+        }
+        let segments = req.resource.path_segments();
+        let matches: Vec<(&Route, Params)> = self
+            .routes
+            .iter()
+            .filter_map(|route| Some((route, route.pattern.matches(&segments)?)))
+            .collect();
+        let best = Self::best(&matches, &req.method).or_else(|| match req.method {
+            Method::Head => Self::best(&matches, &Method::Get),
+            _ => None,
+        });
+        if let Some((route, params)) = best {
+            let endpoint = |req: &mut HttpRequest| route.handler.handle(req, params);
+            return Next::new(&route.middleware, &endpoint).run(req);
+        }
+        // Answered on behalf of the routes for other methods, so their group's
+        // middleware runs as it would for them: that of the most specific one
+        let closest = matches
+            .iter()
+            .max_by_key(|(route, _)| route.pattern.specificity());
+        let closest = match closest {
+            Some((route, _)) => route,
+            None => return self.not_found.handle(req, &Params::default()),
+        };
+        let status = if req.method == Method::Options {
+            StatusCode::NO_CONTENT
+        } else {
+            StatusCode::METHOD_NOT_ALLOWED
+        };
+        let allow = Self::allow(matches.iter().map(|(route, _)| *route));
+        let endpoint = |_: &mut HttpRequest| {
+            HttpResponse::builder()
+                .status(status)
+                .header("Allow", allow.as_str())
+                .build()
+        };
+        Next::new(&closest.middleware, &endpoint).run(req)
+    }
+
+    /// The most specific of the routes for `method` among `matches`
+    fn best<'a>(
+        matches: &'a [(&'a Route, Params)],
+        method: &Method,
+    ) -> Option<(&'a Route, &'a Params)> {
+        matches
+            .iter()
+            .filter(|(route, _)| route.method == *method)
+            .max_by_key(|(route, _)| route.pattern.specificity())
+            .map(|(route, params)| (*route, params))
+    }
+
+    /// The value of an Allow header listing the methods of `routes`, along with
+    /// the ones answered on their behalf
+    fn allow<'a>(routes: impl IntoIterator<Item = &'a Route>) -> String {
+        let mut methods: Vec<&str> = Vec::new();
+        for route in routes {
+            let method = route.method.as_str();
+            if !methods.contains(&method) {
+                methods.push(method);
             }
-            // Method::Post => todo!(),
-            // Method::Invalid => todo!(),
-            _ => {
-                let response: HttpResponse = PageNotFound::handle(&req);
-                response.send_response(&mut stream);
+            if route.method == Method::Get && !methods.contains(&"HEAD") {
+                methods.push("HEAD");
             }
         }
+        if !methods.contains(&"OPTIONS") {
+            methods.push("OPTIONS");
+        }
+        methods.join(", ")
+    }
+}
+
+/// Routes added through a `Group` get its prefix and its middleware. Middleware
+/// only applies to the routes added after it.
+pub struct Group<'r> {
+    router: &'r mut Router,
+    prefix: String,
+    middleware: Vec<Arc<dyn Middleware>>,
+}
+
+impl Group<'_> {
+    /// Run `middleware` for requests routed to this group
+    pub fn wrap(&mut self, middleware: impl Middleware + 'static) -> &mut Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Like `Router::add`, with `pattern` relative to the group's prefix
+    pub fn add(
+        &mut self,
+        method: Method,
+        pattern: &str,
+        handler: impl Handler + 'static,
+    ) -> Result<&mut Self, RouteError> {
+        let pattern = match pattern {
+            "/" if !self.prefix.is_empty() => self.prefix.clone(),
+            _ => format!("{}{}", self.prefix, pattern),
+        };
+        let middleware = self.middleware.clone();
+        self.router
+            .insert(method, &pattern, Box::new(handler), middleware)?;
+        Ok(self)
+    }
+
+    /// Shorthand for `add(Method::Get, ..)`
+    pub fn get(
+        &mut self,
+        pattern: &str,
+        handler: impl Handler + 'static,
+    ) -> Result<&mut Self, RouteError> {
+        self.add(Method::Get, pattern, handler)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+    use std::sync::Mutex;
+
+    fn request(method: &str, target: &str) -> HttpRequest {
+        let raw = format!("{} {} HTTP/1.1\r\n\r\n", method, target);
+        HttpRequest::try_from(raw.as_bytes()).unwrap()
+    }
+
+    /// A handler answering with `name` and the parameters it was given
+    fn named(name: &'static str) -> impl Handler {
+        move |_: &HttpRequest, params: &Params| {
+            let params: Vec<String> = params
+                .pairs
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect();
+            HttpResponse::builder().body(format!("{} {}", name, params.join(" ")))
+        }
+    }
+
+    fn answer(router: &Router, method: &str, target: &str) -> String {
+        let response = router.route(&mut request(method, target));
+        String::from_utf8_lossy(response.body().as_bytes().unwrap_or_default())
+            .into_owned()
+    }
+
+    #[test]
+    fn routes_to_the_most_specific_match() {
+        let mut router = Router::new();
+        router
+            .get("/*path", named("files"))
+            .unwrap()
+            .get("/api/*rest", named("api"))
+            .unwrap()
+            .get("/api/:section", named("section"))
+            .unwrap()
+            .get("/api/orders", named("orders"))
+            .unwrap()
+            .get("/api/orders/:id", named("order"))
+            .unwrap();
+        assert_eq!(answer(&router, "GET", "/api/orders"), "orders ");
+        assert_eq!(
+            answer(&router, "GET", "/api/users"),
+            "section section=users"
+        );
+        assert_eq!(answer(&router, "GET", "/api/orders/7"), "order id=7");
+        assert_eq!(
+            answer(&router, "GET", "/api/users/1/x"),
+            "api rest=users/1/x"
+        );
+        // A wildcard matches nothing at all as well
+        assert_eq!(answer(&router, "GET", "/api"), "api rest=");
+        assert_eq!(answer(&router, "GET", "/apix"), "files path=apix");
+        assert_eq!(answer(&router, "GET", "/"), "files path=");
+        // A parameter needs something to match
+        assert_eq!(answer(&router, "GET", "/api/orders/"), "api rest=orders/");
+    }
+
+    #[test]
+    fn hands_decoded_parameters_to_the_handler() {
+        let mut router = Router::new();
+        router
+            .get("/users/:user/files/*file", named("file"))
+            .unwrap();
+        assert_eq!(
+            answer(&router, "GET", "/users/j%20doe/files/docs/a.txt?download=1"),
+            "file user=j doe file=docs/a.txt"
+        );
+        let response = router.route(&mut request("GET", "/users/j%20doe"));
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn rejects_malformed_patterns() {
+        let mut router = Router::new();
+        for pattern in ["orders", "/*rest/more", "/orders/:", "/*", "/:id/:id"].iter() {
+            assert!(
+                matches!(
+                    router.get(pattern, named("bad")),
+                    Err(RouteError::InvalidPattern { .. })
+                ),
+                "{}",
+                pattern
+            );
+        }
+    }
+
+    #[test]
+    fn answers_other_methods_with_405_and_what_is_allowed() {
+        let mut router = Router::new();
+        router
+            .get("/orders/:id", named("order"))
+            .unwrap()
+            .add(Method::Delete, "/orders/:id", named("cancel"))
+            .unwrap()
+            .add(Method::Post, "/orders/*rest", named("create"))
+            .unwrap();
+        let response = router.route(&mut request("PUT", "/orders/7"));
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(
+            response.headers().get("Allow"),
+            Some("GET, HEAD, DELETE, POST, OPTIONS")
+        );
+        assert_eq!(response.headers().get("Content-type"), None);
+        assert_eq!(answer(&router, "DELETE", "/orders/7"), "cancel id=7");
+
+        let response = router.route(&mut request("PUT", "/customers/7"));
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn answers_options_unless_a_route_does() {
+        let mut router = Router::new();
+        router
+            .add(Method::Post, "/orders", named("create"))
+            .unwrap()
+            .add(Method::Options, "/custom", named("custom"))
+            .unwrap();
+        let response = router.route(&mut request("OPTIONS", "/orders"));
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(response.headers().get("Allow"), Some("POST, OPTIONS"));
+        assert_eq!(answer(&router, "OPTIONS", "/custom"), "custom ");
+    }
+
+    #[test]
+    fn answers_options_for_the_whole_server_itself() {
+        let mut router = Router::new();
+        router
+            .add(Method::Post, "/orders", named("create"))
+            .unwrap()
+            .get("/*path", named("files"))
+            .unwrap()
+            .add(Method::Options, "/*path", named("file options"))
+            .unwrap();
+        // Not for the wildcard route, which would see it as the path `/`
+        let response = router.route(&mut request("OPTIONS", "*"));
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            response.headers().get("Allow"),
+            Some("POST, GET, HEAD, OPTIONS")
+        );
+    }
+
+    #[test]
+    fn answers_head_from_the_get_route() {
+        let mut router = Router::new();
+        router
+            .get("/orders/:id", named("order"))
+            .unwrap()
+            .get("/files/*path", named("file"))
+            .unwrap()
+            .add(Method::Head, "/files/*path", named("file head"))
+            .unwrap();
+        assert_eq!(answer(&router, "HEAD", "/orders/7"), "order id=7");
+        assert_eq!(answer(&router, "HEAD", "/files/a"), "file head path=a");
+    }
+
+    /// Middleware noting when requests pass it on the way in and out
+    fn trace(log: &Arc<Mutex<Vec<String>>>, name: &'static str) -> impl Middleware {
+        let log = Arc::clone(log);
+        move |req: &mut HttpRequest, next: Next<'_>| {
+            log.lock().unwrap().push(format!("{} in", name));
+            let response = next.run(req);
+            log.lock().unwrap().push(format!("{} out", name));
+            response
+        }
+    }
+
+    /// Middleware adding a header to every response
+    fn tag(name: &'static str) -> impl Middleware {
+        move |req: &mut HttpRequest, next: Next<'_>| {
+            let mut response = next.run(req);
+            response.headers_mut().append("X-Tag", name);
+            response
+        }
+    }
+
+    #[test]
+    fn runs_global_middleware_around_the_group_first_added_outermost() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut router = Router::new();
+        router.wrap(trace(&log, "outer")).wrap(trace(&log, "inner"));
+        router
+            .group("/api")
+            .wrap(trace(&log, "first"))
+            .wrap(trace(&log, "second"))
+            .get("/orders", named("orders"))
+            .unwrap();
+        router.get("/*path", named("files")).unwrap();
+
+        assert_eq!(answer(&router, "GET", "/api/orders"), "orders ");
+        let expected = [
+            "outer in",
+            "inner in",
+            "first in",
+            "second in",
+            "second out",
+            "first out",
+            "inner out",
+            "outer out",
+        ];
+        assert_eq!(*log.lock().unwrap(), expected);
+
+        log.lock().unwrap().clear();
+        assert_eq!(
+            answer(&router, "GET", "/index.html"),
+            "files path=index.html"
+        );
+        let expected = ["outer in", "inner in", "inner out", "outer out"];
+        assert_eq!(*log.lock().unwrap(), expected);
+    }
+
+    #[test]
+    fn lets_middleware_answer_or_change_the_request() {
+        let mut router = Router::new();
+        // Requests can be rewritten before they are routed
+        router.wrap(|req: &mut HttpRequest, next: Next<'_>| {
+            if req.resource.path() == "/old" {
+                req.resource = "/new".parse().unwrap();
+            }
+            next.run(req)
+        });
+        router.get("/new", named("new")).unwrap();
+        router
+            .group("/admin")
+            .wrap(|req: &mut HttpRequest, next: Next<'_>| {
+                if req.headers.contains("Authorization") {
+                    next.run(req)
+                } else {
+                    HttpResponse::builder()
+                        .status(StatusCode::UNAUTHORIZED)
+                        .body("who are you?")
+                }
+            })
+            .get("/", named("admin"))
+            .unwrap();
+
+        assert_eq!(answer(&router, "GET", "/old"), "new ");
+        let response = router.route(&mut request("GET", "/admin"));
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.body().as_bytes(), Some(&b"who are you?"[..]));
+    }
+
+    #[test]
+    fn runs_group_middleware_for_answers_on_behalf_of_its_routes() {
+        let mut router = Router::new();
+        router
+            .group("/api")
+            .wrap(tag("api"))
+            .add(Method::Post, "/*rest", named("api"))
+            .unwrap();
+        router
+            .group("/api/orders")
+            .wrap(tag("orders"))
+            .get("/:id", named("order"))
+            .unwrap();
+
+        let preflight = router.route(&mut request("OPTIONS", "/api/orders/7"));
+        assert_eq!(preflight.status(), StatusCode::NO_CONTENT);
+        assert_eq!(preflight.headers().get("X-Tag"), Some("orders"));
+        assert_eq!(
+            preflight.headers().get("Allow"),
+            Some("POST, GET, HEAD, OPTIONS")
+        );
+
+        let response = router.route(&mut request("PUT", "/api/users"));
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers().get("X-Tag"), Some("api"));
+    }
+
+    #[test]
+    fn rejects_routes_covering_the_same_paths() {
+        let mut router = Router::new();
+        router.get("/orders/:id", named("order")).unwrap();
+        let conflict = router.get("/orders/:key", named("again")).err();
+        assert_eq!(
+            conflict,
+            Some(RouteError::Conflict {
+                method: "GET".to_string(),
+                pattern: "/orders/:key".to_string(),
+                existing: "/orders/:id".to_string(),
+            })
+        );
+        assert!(router
+            .add(Method::Post, "/orders/:id", named("update"))
+            .is_ok());
+        assert!(router.get("/orders/new", named("form")).is_ok());
+        assert!(router.get("/orders/*rest", named("rest")).is_ok());
     }
 }
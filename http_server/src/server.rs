@@ -1,58 +1,557 @@
 //! Requests are incident on `Server` provided by this module
 
+use super::connection::{Connection, ReadError, Stage};
+use super::event_loop;
+use super::pool::WorkerPool;
 use super::router::Router;
-use http::http_request::HttpRequest;
-use std::io::{BufReader, Read};
-use std::net::TcpListener;
+use super::shutdown::ShutdownHandle;
+use http::headers::HeaderMap;
+use http::http_request::{HttpRequest, Method, Version};
+use http::http_response::HttpResponse;
+use http::parser::{ParseError, ParserLimits};
+use http::status::StatusCode;
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Default for `Server::max_requests`
+pub const DEFAULT_MAX_REQUESTS: usize = 100;
+/// Default for `Server::threads`
+pub const DEFAULT_THREADS: usize = 8;
+/// Default for `Server::queue_depth`
+pub const DEFAULT_QUEUE_DEPTH: usize = 64;
+/// Default for `Server::drain_timeout`
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often a worker waiting on a quiet client checks the clock and for a
+/// shutdown
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
 /// Struct `Server` created with `Server::new(socket_addr)` will
 /// start listening on `socket_addr` for incoming connections and convert
 /// the incoming byte stream into `http::http_request::HttpRequest` for routing
 pub struct Server<'a> {
     socket_addr: &'a str,
+    settings: Settings,
+    router: Arc<Router>,
+    backend: Backend,
+    threads: usize,
+    queue_depth: usize,
+    shutdown: ShutdownHandle,
+}
+
+/// How connections are spread over threads. Routing and handlers work the same
+/// either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// A pool of worker threads, each serving one connection at a time
+    #[default]
+    Threads,
+    /// A few threads running non-blocking event loops, each juggling many
+    /// connections. Suits lots of mostly idle keep-alive clients.
+    EventLoop,
+}
+
+/// How each connection is handled, whatever the backend
+#[derive(Debug, Clone, Copy)]
+pub struct Settings {
+    pub limits: ParserLimits,
+    pub timeouts: Timeouts,
+    pub max_requests: usize,
+    /// How long requests in progress get to finish once shutdown starts
+    pub drain_timeout: Duration,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            limits: ParserLimits::default(),
+            timeouts: Timeouts::default(),
+            max_requests: DEFAULT_MAX_REQUESTS,
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+        }
+    }
+}
+
+/// How long the server waits on a client before giving up on it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timeouts {
+    /// From the first byte of a request to the end of its headers. Runs out with
+    /// 408 Request Timeout.
+    pub header_read: Duration,
+    /// Longest pause while a request body is coming in. Runs out with 408 too.
+    pub body_read: Duration,
+    /// Slowest a body may arrive, in bytes per second on average, once it has been
+    /// arriving for `body_read`. `None` for no minimum.
+    pub min_body_rate: Option<u64>,
+    /// Longest a response may wait for the client to make room for it
+    pub write: Duration,
+    /// Longest a connection may sit between requests
+    pub idle: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            header_read: Duration::from_secs(10),
+            body_read: Duration::from_secs(20),
+            min_body_rate: Some(500),
+            write: Duration::from_secs(20),
+            idle: Duration::from_secs(5),
+        }
+    }
 }
 
 impl<'a> Server<'a> {
     pub fn new(socket_addr: &'a str) -> Self {
-        Server { socket_addr }
+        Server {
+            socket_addr,
+            settings: Settings::default(),
+            router: Arc::new(Router::new()),
+            backend: Backend::default(),
+            threads: DEFAULT_THREADS,
+            queue_depth: DEFAULT_QUEUE_DEPTH,
+            shutdown: ShutdownHandle::new(),
+        }
+    }
+    /// Route requests with `router` instead of answering them all with 404
+    pub fn router(mut self, router: Router) -> Self {
+        self.router = Arc::new(router);
+        self
+    }
+    /// Replace the default limits on request line, header and body sizes
+    pub fn limits(mut self, limits: ParserLimits) -> Self {
+        self.settings.limits = limits;
+        self
+    }
+    /// Replace the default timeouts on reading requests and writing responses
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.settings.timeouts = timeouts;
+        self
+    }
+    /// Close a connection after answering this many requests on it
+    pub fn max_requests(mut self, max_requests: usize) -> Self {
+        self.settings.max_requests = max_requests.max(1);
+        self
+    }
+    /// Choose how connections are spread over threads
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+    /// Number of worker threads, which is how many connections the `Threads`
+    /// backend serves at once, or the number of event loops
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+    /// Let this many accepted connections wait for a free worker. Any more are
+    /// answered with 503 Service Unavailable. Only the `Threads` backend queues.
+    pub fn queue_depth(mut self, queue_depth: usize) -> Self {
+        self.queue_depth = queue_depth;
+        self
+    }
+    /// Give requests in progress this long to finish after a shutdown
+    pub fn drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.settings.drain_timeout = drain_timeout;
+        self
+    }
+    /// A handle that makes `run` stop accepting, finish what it is doing and
+    /// return
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
     }
     pub fn run(&self) {
-        let connection: TcpListener =
+        let listener: TcpListener =
             TcpListener::bind(self.socket_addr).expect("Failed to bind to port");
         println!("Listening on {}", self.socket_addr);
+        if let Ok(addr) = listener.local_addr() {
+            self.shutdown.listening_on(addr);
+        }
 
-        for stream in connection.incoming() {
-            if let Ok(stream) = stream {
-                println!(
-                    "Connection established with {}",
-                    stream.peer_addr().unwrap()
-                );
-                // we choose a buffer length of 256 bytes because this chapter says so:
-                // https://www.w3.org/Protocols/rfc2616/rfc2616-sec3.html#sec3.2.1
-                let mut buf = [0_u8; 256];
-                match (&mut &stream).read(&mut buf) {
-                    Ok(0) => continue,
-                    Ok(bytes_read) => {
-                        if !(bytes_read <= buf.len()) {
-                            eprintln!("Request too long, aborting");
-                            continue;
-                        }
-                        let request = String::from_utf8(buf.to_vec());
-                        match request {
-                            Ok(req) => {
-                                Router::route(HttpRequest::from(&req), &stream);
-                            }
-                            Err(_) => {
-                                eprintln!("Error parsing HTTP Request");
-                                continue;
-                            }
-                        }
-                    }
-                    Err(_e) => {
-                        eprintln!("Cannot read from socket");
-                        continue;
-                    }
+        match self.backend {
+            Backend::Threads => self.run_threads(listener),
+            Backend::EventLoop => event_loop::run(
+                listener,
+                self.settings,
+                &self.router,
+                self.threads,
+                &self.shutdown,
+            ),
+        }
+        println!("Server stopped");
+    }
+    fn run_threads(&self, listener: TcpListener) {
+        let settings = self.settings;
+        let router = Arc::clone(&self.router);
+        let shutdown = self.shutdown.clone();
+        let pool = WorkerPool::new(self.threads, self.queue_depth, move |stream| {
+            serve(&stream, &settings, &router, &shutdown)
+        });
+        for stream in listener.incoming().flatten() {
+            // Whatever woke us up may be the shutdown handle itself
+            if self.shutdown.is_shutdown() {
+                break;
+            }
+            // A client that resets straight away has no address left to log
+            if let Ok(addr) = stream.peer_addr() {
+                println!("Connection established with {}", addr);
+            }
+            if let Err(stream) = pool.submit(stream) {
+                eprintln!("All workers busy, turning the connection away");
+                overloaded(&stream);
+            }
+        }
+        drop(listener);
+        // Connections still queued are served too: their clients already sent
+        // their requests, and each gets Connection: close
+        let requested_at = self.shutdown.requested_at().unwrap_or_else(Instant::now);
+        let busy = pool.shutdown(requested_at + settings.drain_timeout);
+        if busy > 0 {
+            eprintln!("Gave up on {} connections still busy after draining", busy);
+        }
+    }
+}
+
+/// Answer requests on one connection until either side wants it closed.
+/// Pipelined requests are read one at a time, so they are answered in the
+/// order they arrived.
+fn serve(
+    mut stream: &TcpStream,
+    settings: &Settings,
+    router: &Router,
+    shutdown: &ShutdownHandle,
+) {
+    // Reads wake up now and then to look at the clock and for a shutdown
+    let timeouts = stream
+        .set_read_timeout(Some(POLL_INTERVAL))
+        .and_then(|_| stream.set_write_timeout(Some(settings.timeouts.write)));
+    if let Err(e) = timeouts {
+        eprintln!("Cannot set socket timeout: {}", e);
+        return;
+    }
+    let mut connection = Connection::new(stream, settings.limits, settings.timeouts);
+    let mut served = 0;
+    while let Some(req) = next_request(&mut connection, settings, shutdown) {
+        served += 1;
+        let (response, keep_alive) = respond(router, req, served, settings, shutdown);
+        if response.send_response(&mut stream).is_err() {
+            eprintln!("Cannot write to socket");
+            return;
+        }
+        if !keep_alive {
+            return;
+        }
+    }
+}
+
+/// Wait for the next request on `connection`, or `None` if the connection should
+/// be closed instead: the client closed it, took too long, or the server is
+/// shutting down.
+fn next_request(
+    connection: &mut Connection<&TcpStream>,
+    settings: &Settings,
+    shutdown: &ShutdownHandle,
+) -> Option<HttpRequest> {
+    loop {
+        match connection.read_request() {
+            Ok(req) => return req,
+            Err(ReadError::Parse(e)) => {
+                eprintln!("Error parsing HTTP Request: {}", e);
+                let mut stream = *connection.stream();
+                if rejection(&e).send_response(&mut stream).is_err() {
+                    eprintln!("Cannot write to socket");
+                }
+                return None;
+            }
+            Err(ReadError::Io(e))
+                if e.kind() == io::ErrorKind::WouldBlock
+                    || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                // Between requests the connection can go at once, in the middle of
+                // one the client gets until the drain deadline to finish it
+                let drained = match shutdown.requested_at() {
+                    Some(_) if connection.is_idle() => true,
+                    Some(at) => at.elapsed() >= settings.drain_timeout,
+                    None => false,
+                };
+                if drained {
+                    return None;
                 }
+                match connection.timed_out() {
+                    None => (),
+                    Some(Stage::Idle) => return None,
+                    Some(stage) => return timed_out(connection, stage),
+                }
+            }
+            Err(ReadError::Timeout(stage)) => return timed_out(connection, stage),
+            Err(ReadError::Io(e)) => {
+                eprintln!("Cannot read from socket: {}", e);
+                return None;
             }
         }
     }
 }
+
+/// Answer 408 to a client that took too long over `stage` of its request, before
+/// the connection is closed
+fn timed_out(connection: &Connection<&TcpStream>, stage: Stage) -> Option<HttpRequest> {
+    eprintln!("Timed out reading the request ({:?})", stage);
+    let mut stream = *connection.stream();
+    if request_timeout().send_response(&mut stream).is_err() {
+        eprintln!("Cannot write to socket");
+    }
+    None
+}
+
+/// Route `req` with `router` and decide whether the connection stays open
+/// afterwards, `req` being the `served`th request on it. The response says so in
+/// its Connection header. Once `shutdown` has been asked for, connections are
+/// closed after the response, even when that happened while the handler ran.
+pub fn respond(
+    router: &Router,
+    mut req: HttpRequest,
+    served: usize,
+    settings: &Settings,
+    shutdown: &ShutdownHandle,
+) -> (HttpResponse, bool) {
+    // Go by what the client sent, whatever middleware makes of the request
+    let (method, version) = (req.method.clone(), req.version);
+    let wants_keep_alive = wants_keep_alive(&req);
+    let mut response = router.route(&mut req);
+    // Whatever answered, a HEAD request never gets a body (RFC 9110, section 9.3.2)
+    if method == Method::Head {
+        response.omit_body();
+    }
+    // An HTTP/1.0 client can't decode chunked bodies, so answer in its version
+    if version == Version::V1_0 {
+        response.set_version(Version::V1_0);
+    }
+    let keep_alive = wants_keep_alive
+        && !shutdown.is_shutdown()
+        && served < settings.max_requests
+        && !response.is_close_delimited();
+    if !keep_alive {
+        response.headers_mut().insert("Connection", "close");
+    } else if version == Version::V1_0 {
+        response.headers_mut().insert("Connection", "keep-alive");
+    }
+    (response, keep_alive)
+}
+
+/// HTTP/1.1 connections stay open unless the client says otherwise, HTTP/1.0 ones
+/// only when the client asks for it (RFC 9112, section 9.3)
+fn wants_keep_alive(req: &HttpRequest) -> bool {
+    match req.version {
+        Version::V1_1 => !req.headers.has_token("Connection", "close"),
+        _ => req.headers.has_token("Connection", "keep-alive"),
+    }
+}
+
+/// Tell the client why its request couldn't be parsed. The connection has to be
+/// closed afterwards, since there's no telling where the next request would start.
+pub fn rejection(err: &ParseError) -> HttpResponse {
+    let status = match err {
+        ParseError::RequestLineTooLong => StatusCode::URI_TOO_LONG,
+        ParseError::TooManyHeaders
+        | ParseError::HeaderSectionTooLarge
+        | ParseError::TrailersTooLarge => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+        ParseError::BodyTooLarge => StatusCode::CONTENT_TOO_LARGE,
+        ParseError::UnsupportedVersion => StatusCode::HTTP_VERSION_NOT_SUPPORTED,
+        ParseError::UnsupportedTransferEncoding => StatusCode::NOT_IMPLEMENTED,
+        _ => StatusCode::BAD_REQUEST,
+    };
+    let mut headers = HeaderMap::new();
+    headers.insert("Content-type", "text/plain");
+    headers.insert("Connection", "close");
+    HttpResponse::new(status, Some(headers), Some(err.to_string()))
+}
+
+/// Tell the client it took too long to send its request
+pub fn request_timeout() -> HttpResponse {
+    let mut headers = HeaderMap::new();
+    headers.insert("Content-type", "text/plain");
+    headers.insert("Connection", "close");
+    let body = "Timed out waiting for the request";
+    HttpResponse::new(StatusCode::REQUEST_TIMEOUT, Some(headers), Some(body.into()))
+}
+
+/// Turn a connection away because no worker is free to serve it. This runs on the
+/// accept loop, so a client that doesn't read its answer mustn't hold it up.
+fn overloaded(mut stream: &TcpStream) {
+    let mut headers = HeaderMap::new();
+    headers.insert("Content-type", "text/plain");
+    headers.insert("Retry-After", "1");
+    headers.insert("Connection", "close");
+    let body = "The server is too busy to answer, try again shortly";
+    let response =
+        HttpResponse::new(StatusCode::SERVICE_UNAVAILABLE, Some(headers), Some(body.into()));
+    let sent = stream
+        .set_write_timeout(Some(Duration::from_secs(1)))
+        .and_then(|_| response.send_response(&mut stream));
+    if sent.is_err() {
+        eprintln!("Cannot write to socket");
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::router::Params;
+    use http::http_response::Body;
+    use std::convert::TryFrom;
+    use std::io::{Read, Write};
+    use std::net::SocketAddr;
+    use std::thread::{self, JoinHandle};
+
+    /// Run `server` on a thread of its own, returning where it listens, its shutdown
+    /// handle and the thread, which ends when `run` returns
+    pub(crate) fn start(
+        server: Server<'static>,
+    ) -> (SocketAddr, ShutdownHandle, JoinHandle<()>) {
+        let shutdown = server.shutdown_handle();
+        let running = thread::spawn(move || server.run());
+        loop {
+            if let Some(addr) = shutdown.listening() {
+                return (addr, shutdown, running);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn request(raw: &str) -> HttpRequest {
+        HttpRequest::try_from(raw.as_bytes()).unwrap()
+    }
+
+    /// Answers every GET with the path it was asked for
+    fn echo() -> Router {
+        let mut router = Router::new();
+        router
+            .get("/*path", |req: &HttpRequest, _: &Params| {
+                HttpResponse::builder().body(req.resource.path().to_string())
+            })
+            .unwrap();
+        router
+    }
+
+    #[test]
+    fn keeps_http_1_1_connections_open_unless_told_otherwise() {
+        let (router, settings) = (echo(), Settings::default());
+        let shutdown = ShutdownHandle::new();
+        let req = request("GET / HTTP/1.1\r\n\r\n");
+        let (response, keep_alive) = respond(&router, req, 1, &settings, &shutdown);
+        assert!(keep_alive);
+        assert_eq!(response.headers().get("Connection"), None);
+
+        let req = request("GET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+        let (response, keep_alive) = respond(&router, req, 1, &settings, &shutdown);
+        assert!(!keep_alive);
+        assert_eq!(response.headers().get("Connection"), Some("close"));
+    }
+
+    #[test]
+    fn keeps_http_1_0_connections_open_only_when_asked() {
+        let (router, settings) = (echo(), Settings::default());
+        let shutdown = ShutdownHandle::new();
+        let req = request("GET / HTTP/1.0\r\n\r\n");
+        let (response, keep_alive) = respond(&router, req, 1, &settings, &shutdown);
+        assert!(!keep_alive);
+        assert_eq!(response.version(), Version::V1_0);
+        assert_eq!(response.headers().get("Connection"), Some("close"));
+
+        let req = request("GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n");
+        let (response, keep_alive) = respond(&router, req, 1, &settings, &shutdown);
+        assert!(keep_alive);
+        assert_eq!(response.headers().get("Connection"), Some("keep-alive"));
+    }
+
+    #[test]
+    fn closes_after_max_requests_while_draining_or_without_framing() {
+        let settings = Settings {
+            max_requests: 3,
+            ..Settings::default()
+        };
+        let (router, shutdown) = (echo(), ShutdownHandle::new());
+        let get = || request("GET / HTTP/1.1\r\n\r\n");
+        assert!(respond(&router, get(), 2, &settings, &shutdown).1);
+        assert!(!respond(&router, get(), 3, &settings, &shutdown).1);
+        let draining = ShutdownHandle::new();
+        draining.shutdown();
+        assert!(!respond(&router, get(), 1, &settings, &draining).1);
+
+        // HTTP/1.0 can't take a chunked body, so the end of the connection ends it
+        let mut streams = Router::new();
+        streams
+            .get("/", |_: &HttpRequest, _: &Params| {
+                HttpResponse::builder().body(Body::from_reader(&b"..."[..], None))
+            })
+            .unwrap();
+        let req = request("GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n");
+        assert!(!respond(&streams, req, 1, &settings, &shutdown).1);
+    }
+
+    #[test]
+    fn rejects_requests_over_the_limits_with_matching_statuses() {
+        let too_large = "431 Request Header Fields Too Large";
+        let cases = [
+            (ParseError::RequestLineTooLong, "414 URI Too Long"),
+            (ParseError::TooManyHeaders, too_large),
+            (ParseError::HeaderSectionTooLarge, too_large),
+            (ParseError::TrailersTooLarge, too_large),
+            (ParseError::BodyTooLarge, "413 Content Too Large"),
+            (ParseError::InvalidHeader, "400 Bad Request"),
+        ];
+        for (err, status) in cases.iter() {
+            let mut written = Vec::new();
+            rejection(err).send_response(&mut written).unwrap();
+            let written = String::from_utf8(written).unwrap();
+            let status_line = format!("HTTP/1.1 {}\r\n", status);
+            assert!(written.starts_with(&status_line), "{:?}: {}", err, written);
+            assert!(written.contains("\r\nConnection: close\r\n"), "{:?}", err);
+        }
+    }
+
+    #[test]
+    fn answers_pipelined_requests_in_order() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let server = thread::spawn(move || {
+            serve(&stream, &Settings::default(), &echo(), &ShutdownHandle::new())
+        });
+        client
+            .write_all(
+                b"GET /first HTTP/1.1\r\n\r\nGET /second HTTP/1.1\r\n\r\n\
+                  GET /third HTTP/1.1\r\nConnection: close\r\n\r\n",
+            )
+            .unwrap();
+        let mut answers = String::new();
+        client.read_to_string(&mut answers).unwrap();
+        server.join().unwrap();
+        let bodies: Vec<&str> = answers
+            .split("HTTP/1.1 200 OK")
+            .skip(1)
+            .map(|answer| answer.rsplit("\r\n\r\n").next().unwrap())
+            .collect();
+        assert_eq!(bodies, ["/first", "/second", "/third"]);
+    }
+
+    #[test]
+    fn answers_503_when_no_worker_is_free() {
+        let server = Server::new("127.0.0.1:0").threads(1).queue_depth(0);
+        let (addr, shutdown, running) = start(server);
+        // Once it has answered, the only worker is busy waiting on this connection
+        let mut busy = TcpStream::connect(addr).unwrap();
+        busy.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert!(busy.read(&mut [0; 512]).unwrap() > 0);
+
+        let mut turned_away = TcpStream::connect(addr).unwrap();
+        let mut answer = String::new();
+        turned_away.read_to_string(&mut answer).unwrap();
+        assert!(answer.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(answer.contains("\r\nRetry-After: 1\r\n"));
+
+        shutdown.shutdown();
+        running.join().unwrap();
+    }
+}
@@ -0,0 +1,217 @@
+//! Stopping the server without cutting off the requests it is answering
+//!
+//! `ShutdownHandle::shutdown` tells `Server::run` to stop accepting connections.
+//! Connections that are between requests are closed at once; the others get until
+//! the drain deadline to finish the request they are on, after which `run` returns.
+
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::io;
+use std::net::{SocketAddr, TcpStream};
+use std::process;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Stops a running `Server`. Clones control the same server.
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownHandle {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// When shutdown was asked for
+    requested: OnceLock<Instant>,
+    /// Where the server listens, so that a blocked `accept` can be woken up
+    listening: Mutex<Option<SocketAddr>>,
+}
+
+impl ShutdownHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask the server to shut down. Returns straight away, `Server::run` returns
+    /// once it is done draining.
+    pub fn shutdown(&self) {
+        if self.inner.requested.set(Instant::now()).is_err() {
+            return;
+        }
+        // A connection of our own gets the accept loop to notice
+        if let Some(addr) = self.listening() {
+            let _ = TcpStream::connect_timeout(&addr, Duration::from_secs(1));
+        }
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.inner.requested.get().is_some()
+    }
+
+    /// When shutdown was asked for, if it has been
+    pub fn requested_at(&self) -> Option<Instant> {
+        self.inner.requested.get().copied()
+    }
+
+    /// Tell the handle where the server is listening
+    pub fn listening_on(&self, addr: SocketAddr) {
+        *self.inner.listening.lock().unwrap() = Some(addr);
+    }
+
+    /// Where the server is listening, once it is
+    pub fn listening(&self) -> Option<SocketAddr> {
+        *self.inner.listening.lock().unwrap()
+    }
+}
+
+/// Shut down through `handle` on SIGINT or SIGTERM. A second signal exits on the
+/// spot, for when draining takes longer than whoever sent it is willing to wait.
+pub fn on_signals(handle: ShutdownHandle) -> io::Result<()> {
+    let mut signals = Signals::new([SIGINT, SIGTERM])?;
+    thread::Builder::new()
+        .name("signals".to_string())
+        .spawn(move || {
+            let mut signals = signals.forever();
+            if signals.next().is_some() {
+                println!("Shutting down, signal again to stop at once");
+                handle.shutdown();
+            }
+            if signals.next().is_some() {
+                process::exit(1);
+            }
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::{Params, Router};
+    use crate::server::tests::start;
+    use crate::server::{Backend, Server};
+    use http::http_request::HttpRequest;
+    use http::http_response::HttpResponse;
+    use std::io::{Read, Write};
+    use std::sync::mpsc;
+
+    /// Answers `/slow` once `release` says so, after telling `started` it began,
+    /// and everything else straight away
+    fn server(
+        backend: Backend,
+        started: mpsc::Sender<()>,
+        release: mpsc::Receiver<()>,
+    ) -> Server<'static> {
+        let (started, release) = (Mutex::new(started), Mutex::new(release));
+        let mut router = Router::new();
+        router
+            .get("/slow", move |_: &HttpRequest, _: &Params| {
+                started.lock().unwrap().send(()).unwrap();
+                let _ = release.lock().unwrap().recv();
+                HttpResponse::builder().body("slow")
+            })
+            .unwrap()
+            .get("/*path", |_: &HttpRequest, _: &Params| {
+                HttpResponse::builder().body("fast")
+            })
+            .unwrap();
+        Server::new("127.0.0.1:0")
+            .backend(backend)
+            .threads(2)
+            .router(router)
+    }
+
+    fn connect(addr: SocketAddr) -> TcpStream {
+        let client = TcpStream::connect(addr).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client
+    }
+
+    /// Whether the server closed the connection, rather than leaving the client
+    /// waiting
+    fn closed(client: &mut TcpStream) -> bool {
+        match client.read(&mut [0; 64]) {
+            Ok(0) => true,
+            Ok(_) => false,
+            Err(e) => !matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ),
+        }
+    }
+
+    fn drains_then_stops(backend: Backend) {
+        let (started, on_start) = mpsc::channel();
+        let (release, gate) = mpsc::channel();
+        let (addr, shutdown, running) = start(server(backend, started, gate));
+
+        let mut idle = connect(addr);
+        idle.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert!(idle.read(&mut [0; 512]).unwrap() > 0);
+        let mut busy = connect(addr);
+        busy.write_all(b"GET /slow HTTP/1.1\r\n\r\n").unwrap();
+        on_start.recv().unwrap();
+
+        shutdown.shutdown();
+        // A newcomer is refused, or closed once the listener goes
+        let newcomer = TcpStream::connect(addr).map(|mut client| {
+            client
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let _ = client.write_all(b"GET / HTTP/1.1\r\n\r\n");
+            client
+        });
+        release.send(()).unwrap();
+
+        let mut answer = String::new();
+        busy.read_to_string(&mut answer).unwrap();
+        assert!(answer.starts_with("HTTP/1.1 200 OK\r\n"), "{}", answer);
+        assert!(answer.contains("\r\nConnection: close\r\n"), "{}", answer);
+        assert!(answer.ends_with("\r\n\r\nslow"));
+        assert!(closed(&mut idle));
+        if let Ok(mut newcomer) = newcomer {
+            assert!(closed(&mut newcomer));
+        }
+        running.join().unwrap();
+    }
+
+    fn gives_up_on_requests_past_the_deadline(backend: Backend) {
+        let (started, _) = mpsc::channel();
+        let (_, gate) = mpsc::channel();
+        let drain_timeout = Duration::from_millis(300);
+        let server = server(backend, started, gate).drain_timeout(drain_timeout);
+        let (addr, shutdown, running) = start(server);
+
+        let mut stuck = connect(addr);
+        stuck.write_all(b"GET / HTTP/1.1\r\nHost: exa").unwrap();
+        // Give the server time to read the start of the request
+        thread::sleep(Duration::from_millis(100));
+        let requested_at = Instant::now();
+        shutdown.shutdown();
+        running.join().unwrap();
+        assert!(requested_at.elapsed() >= drain_timeout);
+        assert!(requested_at.elapsed() < Duration::from_secs(3));
+        assert!(closed(&mut stuck));
+    }
+
+    #[test]
+    fn threads_drain_then_stop() {
+        drains_then_stops(Backend::Threads);
+    }
+
+    #[test]
+    fn event_loops_drain_then_stop() {
+        drains_then_stops(Backend::EventLoop);
+    }
+
+    #[test]
+    fn threads_give_up_on_requests_past_the_deadline() {
+        gives_up_on_requests_past_the_deadline(Backend::Threads);
+    }
+
+    #[test]
+    fn event_loops_give_up_on_requests_past_the_deadline() {
+        gives_up_on_requests_past_the_deadline(Backend::EventLoop);
+    }
+}
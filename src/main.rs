@@ -1,4 +1,3 @@
-use http::*;
 fn main() {
     println!("Hello, world!");
 }
@@ -14,13 +14,13 @@ fn main() {
             .expect("Failed to read from STDIN");
         // println!("Sending \"{}\" ...", package);
 
-        if package.trim() == "quit".to_string() {
+        if package.trim() == "quit" {
             println!("Exiting ...");
             std::process::exit(0);
         }
         // Send `package` as bytes into stream
-        let bytes_sent = server
-            .write(&mut package.as_bytes())
+        server
+            .write_all(package.as_bytes())
             .expect("Failed to write to server");
 
         package.clear();